@@ -0,0 +1,961 @@
+use crate::error::Chip8Error;
+use crate::instruction::{encode, Instruction};
+use std::collections::{HashMap, VecDeque};
+
+const PROGRAM_START: usize = 0x200;
+// highest address XO-CHIP can load into
+const MAX_ADDRESS: i64 = 0xFFFF;
+// expansions until a macro that invokes itself is given up on
+const MAX_EXPANSIONS: usize = 0x10000;
+
+const CALC_OPERATORS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=",
+    "==", "!=",
+];
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Part of the rom that is written once a label is defined
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    // low 12 bits of the instruction, e.g. jump, :call, i :=
+    Address,
+    // the word following F000 (i := long)
+    Long,
+}
+
+struct Fixup {
+    address: usize,
+    name: String,
+    line: usize,
+    kind: FixupKind,
+}
+
+// Open structured statement, closed by end or again
+enum Block {
+    If {
+        jump: usize,
+        line: usize,
+    },
+    Else {
+        jump: usize,
+        line: usize,
+    },
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+        line: usize,
+    },
+}
+
+/*
+Condition:
+    - comparison of an if or while
+    prelude:
+        - instructions computing the comparison into var[F] (<, >, <=, >=)
+    skip_unless:
+        - skips the next instruction if the comparison is false (if ... then)
+    skip_if:
+        - skips the next instruction if the comparison is true (if ... begin, while)
+*/
+struct Condition {
+    prelude: Vec<Instruction>,
+    skip_unless: Instruction,
+    skip_if: Instruction,
+}
+
+/*
+Assembler:
+    - single pass over Octo source, references to later labels are fixed up at the end
+    tokens:
+        - whitespace separated words of the source, macro bodies are expanded into the front
+    line:
+        - source line of the last token taken, for error messages
+    rom:
+        - the program, loaded at 0x200
+    here:
+        - address of the next emitted byte
+    labels / constants / aliases / macros:
+        - names defined so far by ":", ":const" and ":calc", ":alias" and ":macro"
+*/
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+// assemble Octo source into a rom
+pub fn assemble(source: &str) -> Result<Vec<u8>, Chip8Error> {
+    Assembler::new(source).run()
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(index, line)| {
+                let code = line.split('#').next().unwrap_or_default();
+                code.split_whitespace().map(move |text| Token {
+                    text: text.to_string(),
+                    line: index + 1,
+                })
+            })
+            .collect();
+        Assembler {
+            tokens,
+            line: 1,
+            rom: Vec::new(),
+            here: PROGRAM_START,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, Chip8Error> {
+        // like Octo, execution starts at main unless it is the first thing in the program
+        let main_first = self
+            .tokens
+            .iter()
+            .take(2)
+            .map(|token| &token.text[..])
+            .eq([":", "main"]);
+        let has_main = self
+            .tokens
+            .iter()
+            .zip(self.tokens.iter().skip(1))
+            .any(|(colon, name)| colon.text == ":" && name.text == "main");
+        if has_main && !main_first {
+            self.fixups.push(Fixup {
+                address: self.here,
+                name: "main".to_string(),
+                line: 1,
+                kind: FixupKind::Address,
+            });
+            self.instruction(Instruction::Jump { nnn: 0 })?;
+        }
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(token.text)?;
+        }
+        if let Some(block) = self.blocks.pop() {
+            let (line, message) = match block {
+                Block::If { line, .. } | Block::Else { line, .. } => (line, "begin without end"),
+                Block::Loop { line, .. } => (line, "loop without again"),
+            };
+            return Err(Chip8Error::Assembly {
+                line,
+                message: message.to_string(),
+            });
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let Some(value) = self.symbol(&fixup.name) else {
+                return Err(self.error(format!("undefined label: {}", fixup.name)));
+            };
+            let offset = fixup.address - PROGRAM_START;
+            match fixup.kind {
+                FixupKind::Address => {
+                    let nnn = self.in_range(value, 0xFFF)? as u16;
+                    let op_code = (self.rom[offset] as u16) << 8 & 0xF000 | nnn;
+                    self.rom[offset..offset + 2].copy_from_slice(&op_code.to_be_bytes());
+                }
+                FixupKind::Long => {
+                    let address = self.in_range(value, MAX_ADDRESS)? as u16;
+                    self.rom[offset..offset + 2].copy_from_slice(&address.to_be_bytes());
+                }
+            }
+        }
+        Ok(self.rom)
+    }
+
+    fn statement(&mut self, text: String) -> Result<(), Chip8Error> {
+        match &text[..] {
+            ":" => {
+                let name = self.name()?;
+                self.define(&name)?;
+                self.labels.insert(name, self.here as u16);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self.value(&token.text)?;
+                self.define(&name)?;
+                self.constants.insert(name, value as f64);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.expect("}")?;
+                self.define(&name)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.define(&name)?;
+                self.aliases.insert(name, register);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                loop {
+                    let token = self.next()?;
+                    if token.text == "{" {
+                        break;
+                    }
+                    params.push(token.text);
+                }
+                let body = self.braced()?;
+                self.define(&name)?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit(&[byte])?;
+            }
+            ":org" => {
+                let token = self.next()?;
+                let address = self.value(&token.text)?;
+                if address < PROGRAM_START as i64 {
+                    return Err(self.error(format!("{} is below 0x200", token.text)));
+                }
+                self.here = self.in_range(address, MAX_ADDRESS)? as usize;
+            }
+            ":call" => self.address_instruction(|nnn| Instruction::Call { nnn })?,
+            // debugger hint, there is nothing to emit
+            ":breakpoint" => {
+                self.name()?;
+            }
+            ";" | "return" => self.instruction(Instruction::Return)?,
+            "clear" => self.instruction(Instruction::ClearScreen)?,
+            "exit" => self.instruction(Instruction::Exit)?,
+            "lores" => self.instruction(Instruction::LowRes)?,
+            "hires" => self.instruction(Instruction::HighRes)?,
+            "scroll-left" => self.instruction(Instruction::ScrollLeft)?,
+            "scroll-right" => self.instruction(Instruction::ScrollRight)?,
+            "audio" => self.instruction(Instruction::LoadAudioPattern)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollDown { n })?
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollUp { n })?
+            }
+            "plane" => {
+                let x = self.nibble()?;
+                self.instruction(Instruction::SelectPlanes { x })?
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(Instruction::ToDigits { x })?
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    match &text[..] {
+                        "save" => Instruction::SaveRange { x, y },
+                        _ => Instruction::LoadRange { x, y },
+                    }
+                } else {
+                    match &text[..] {
+                        "save" => Instruction::StoreRegisters { x },
+                        _ => Instruction::LoadRegisters { x },
+                    }
+                };
+                self.instruction(instruction)?
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::StoreFlags { x })?
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LoadFlags { x })?
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(Instruction::DrawSprite { x, y, n })?
+            }
+            "jump" => self.address_instruction(|nnn| Instruction::Jump { nnn })?,
+            "jump0" => self.address_instruction(|nnn| Instruction::JumpWithOffset { x: 0, nnn })?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.instruction(match &text[..] {
+                    "delay" => Instruction::SetDelay { x },
+                    "buzzer" => Instruction::SetSound { x },
+                    _ => Instruction::SetPitch { x },
+                })?
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let line = self.line;
+                let condition = self.condition()?;
+                for instruction in condition.prelude {
+                    self.instruction(instruction)?;
+                }
+                let token = self.next()?;
+                match &token.text[..] {
+                    "then" => self.instruction(condition.skip_unless)?,
+                    "begin" => {
+                        self.instruction(condition.skip_if)?;
+                        let jump = self.here;
+                        self.instruction(Instruction::Jump { nnn: 0 })?;
+                        self.blocks.push(Block::If { jump, line });
+                    }
+                    other => {
+                        return Err(self.error(format!("expected then or begin, found {}", other)))
+                    }
+                }
+            }
+            "else" => {
+                let Some(Block::If { jump, line }) = self.blocks.pop() else {
+                    return Err(self.error("else without if ... begin"));
+                };
+                let skip = self.here;
+                self.instruction(Instruction::Jump { nnn: 0 })?;
+                self.patch_jump(jump, self.here)?;
+                self.blocks.push(Block::Else { jump: skip, line });
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. } | Block::Else { jump, .. }) => {
+                    self.patch_jump(jump, self.here)?
+                }
+                _ => return Err(self.error("end without if ... begin")),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                breaks: Vec::new(),
+                line: self.line,
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                for instruction in condition.prelude {
+                    self.instruction(instruction)?;
+                }
+                self.instruction(condition.skip_if)?;
+                let jump = self.here;
+                self.instruction(Instruction::Jump { nnn: 0 })?;
+                let innermost_loop = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                });
+                match innermost_loop {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err(self.error("while outside of a loop")),
+                }
+            }
+            "again" => {
+                let Some(Block::Loop { start, breaks, .. }) = self.blocks.pop() else {
+                    return Err(self.error("again without loop"));
+                };
+                let nnn = self.in_range(start as i64, 0xFFF)? as u16;
+                self.instruction(Instruction::Jump { nnn })?;
+                for jump in breaks {
+                    self.patch_jump(jump, self.here)?;
+                }
+            }
+            _ => {
+                if let Some(value) = parse_number(&text) {
+                    let byte = self.byte_in_range(value)?;
+                    self.emit(&[byte])?;
+                } else if let Some(x) = self.register_named(&text) {
+                    self.register_statement(x)?;
+                } else if self.macros.contains_key(&text) {
+                    self.expand(&text)?;
+                } else if let Some(&value) = self.constants.get(&text) {
+                    let byte = self.byte_in_range(value.floor() as i64)?;
+                    self.emit(&[byte])?;
+                } else if is_identifier(&text) {
+                    // a bare label calls it
+                    self.tokens.push_front(Token {
+                        text,
+                        line: self.line,
+                    });
+                    self.address_instruction(|nnn| Instruction::Call { nnn })?;
+                } else {
+                    return Err(self.error(format!("unexpected {}", text)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // vX := ..., vX += ... and the other operators with a register on the left
+    fn register_statement(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let operator = self.next()?.text;
+        let operand = self.next()?;
+        if let Some(y) = self.register_named(&operand.text) {
+            let instruction = match &operator[..] {
+                ":=" => Instruction::Set { x, y },
+                "+=" => Instruction::Add { x, y },
+                "-=" => Instruction::SubXY { x, y },
+                "=-" => Instruction::SubYX { x, y },
+                "|=" => Instruction::Or { x, y },
+                "&=" => Instruction::And { x, y },
+                "^=" => Instruction::Xor { x, y },
+                ">>=" => Instruction::ShiftRight { x, y },
+                "<<=" => Instruction::ShiftLeft { x, y },
+                _ => return Err(self.error(format!("unknown operator: {}", operator))),
+            };
+            return self.instruction(instruction);
+        }
+        let instruction = match (&operator[..], &operand.text[..]) {
+            (":=", "random") => Instruction::Random {
+                x,
+                nn: self.byte()?,
+            },
+            (":=", "delay") => Instruction::GetDelay { x },
+            (":=", "key") => Instruction::WaitForKey { x },
+            (":=", _) => Instruction::SetImm {
+                x,
+                nn: self.byte_value(&operand.text)?,
+            },
+            ("+=", _) => Instruction::AddImm {
+                x,
+                nn: self.byte_value(&operand.text)?,
+            },
+            ("-=", _) => Instruction::AddImm {
+                x,
+                nn: self.byte_value(&operand.text)?.wrapping_neg(),
+            },
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", _) => {
+                return Err(self.error(format!(
+                    "{} needs a register, found {}",
+                    operator, operand.text
+                )))
+            }
+            _ => return Err(self.error(format!("unknown operator: {}", operator))),
+        };
+        self.instruction(instruction)
+    }
+
+    fn index_statement(&mut self) -> Result<(), Chip8Error> {
+        let operator = self.next()?.text;
+        match &operator[..] {
+            ":=" => match self.peek() {
+                Some("long") => {
+                    self.next()?;
+                    let token = self.next()?;
+                    let address = self.address(token, self.here + 2, FixupKind::Long)?;
+                    self.instruction(Instruction::LoadLongIndex)?;
+                    self.emit(&address.to_be_bytes())
+                }
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.instruction(Instruction::FontChar { x })
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.instruction(Instruction::BigFontChar { x })
+                }
+                _ => self.address_instruction(|nnn| Instruction::SetIndex { nnn }),
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.instruction(Instruction::AddToIndex { x })
+            }
+            _ => Err(self.error(format!("unknown operator for i: {}", operator))),
+        }
+    }
+
+    // vX == vY, vX < NN, vX key, ... of an if or while
+    fn condition(&mut self) -> Result<Condition, Chip8Error> {
+        let x = self.register()?;
+        let operator = self.next()?.text;
+        let plain = |skip_unless, skip_if| Condition {
+            prelude: Vec::new(),
+            skip_unless,
+            skip_if,
+        };
+        match &operator[..] {
+            "key" => {
+                return Ok(plain(
+                    Instruction::SkipIfNotKey { x },
+                    Instruction::SkipIfKey { x },
+                ))
+            }
+            "-key" => {
+                return Ok(plain(
+                    Instruction::SkipIfKey { x },
+                    Instruction::SkipIfNotKey { x },
+                ))
+            }
+            _ => {}
+        }
+
+        let operand = self.next()?;
+        let y = self.register_named(&operand.text);
+        let nn = match y {
+            Some(_) => 0,
+            None => self.byte_value(&operand.text)?,
+        };
+        let condition = match (&operator[..], y) {
+            ("==", Some(y)) => plain(
+                Instruction::SkipIfNeq { x, y },
+                Instruction::SkipIfEq { x, y },
+            ),
+            ("!=", Some(y)) => plain(
+                Instruction::SkipIfEq { x, y },
+                Instruction::SkipIfNeq { x, y },
+            ),
+            ("==", None) => plain(
+                Instruction::SkipIfNeqImm { x, nn },
+                Instruction::SkipIfEqImm { x, nn },
+            ),
+            ("!=", None) => plain(
+                Instruction::SkipIfEqImm { x, nn },
+                Instruction::SkipIfNeqImm { x, nn },
+            ),
+            ("<" | ">" | "<=" | ">=", _) => {
+                // var[F] := operand, then subtract and test the borrow flag
+                let load = match y {
+                    Some(y) => Instruction::Set { x: 0xF, y },
+                    None => Instruction::SetImm { x: 0xF, nn },
+                };
+                let subtract = match &operator[..] {
+                    // vX - operand
+                    "<" | ">=" => Instruction::SubYX { x: 0xF, y: x },
+                    // operand - vX
+                    _ => Instruction::SubXY { x: 0xF, y: x },
+                };
+                let no_borrow = Instruction::SkipIfEqImm { x: 0xF, nn: 1 };
+                let borrow = Instruction::SkipIfEqImm { x: 0xF, nn: 0 };
+                let (skip_unless, skip_if) = match &operator[..] {
+                    "<" | ">" => (no_borrow, borrow),
+                    _ => (borrow, no_borrow),
+                };
+                Condition {
+                    prelude: vec![load, subtract],
+                    skip_unless,
+                    skip_if,
+                }
+            }
+            _ => return Err(self.error(format!("unknown comparison: {}", operator))),
+        };
+        Ok(condition)
+    }
+
+    // expression of a :calc, Octo evaluates right to left without precedence
+    fn calc(&mut self) -> Result<f64, Chip8Error> {
+        let left = self.calc_term()?;
+        let Some(operator) = self
+            .peek()
+            .filter(|operator| CALC_OPERATORS.contains(operator))
+            .map(str::to_string)
+        else {
+            return Ok(left);
+        };
+        self.next()?;
+        let right = self.calc()?;
+        let int = |value: f64| value.floor() as i64;
+        let bool = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match &operator[..] {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => int(left).checked_shl(int(right) as u32).unwrap_or_default() as f64,
+            ">>" => int(left).checked_shr(int(right) as u32).unwrap_or_default() as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => bool(left < right),
+            ">" => bool(left > right),
+            "<=" => bool(left <= right),
+            ">=" => bool(left >= right),
+            "==" => bool(left == right),
+            _ => bool(left != right),
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, Chip8Error> {
+        let token = self.next()?;
+        let value = match &token.text[..] {
+            "(" => {
+                let value = self.calc()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()?.floor() as i64) as f64,
+            "!" => (self.calc_term()? == 0.0) as u8 as f64,
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "tan" => self.calc_term()?.tan(),
+            "exp" => self.calc_term()?.exp(),
+            "log" => self.calc_term()?.ln(),
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "sign" => self.calc_term()?.signum(),
+            "ceil" => self.calc_term()?.ceil(),
+            "floor" => self.calc_term()?.floor(),
+            // byte already assembled at an address
+            "@" => {
+                let address = self.calc_term()?.floor() as usize;
+                let byte = address
+                    .checked_sub(PROGRAM_START)
+                    .and_then(|offset| self.rom.get(offset));
+                *byte.unwrap_or(&0) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            text => match self.constants.get(text) {
+                Some(&value) => value,
+                None => self.value(text)? as f64,
+            },
+        };
+        Ok(value)
+    }
+
+    // a jump, call or i := whose target may be defined later
+    fn address_instruction(
+        &mut self,
+        instruction: fn(u16) -> Instruction,
+    ) -> Result<(), Chip8Error> {
+        let token = self.next()?;
+        let nnn = self.address(token, self.here, FixupKind::Address)?;
+        if nnn > 0xFFF {
+            return Err(self.error(format!("{:#x} is out of the 12 bit address range", nnn)));
+        }
+        self.instruction(instruction(nnn))
+    }
+
+    // value of an address operand, 0 and a fixup at `fixup_address` if it isn't defined yet
+    fn address(
+        &mut self,
+        token: Token,
+        fixup_address: usize,
+        kind: FixupKind,
+    ) -> Result<u16, Chip8Error> {
+        if let Some(value) = self.symbol(&token.text) {
+            return Ok(self.in_range(value, MAX_ADDRESS)? as u16);
+        }
+        if !is_identifier(&token.text) || self.register_named(&token.text).is_some() {
+            return Err(self.error(format!("expected an address, found {}", token.text)));
+        }
+        self.fixups.push(Fixup {
+            address: fixup_address,
+            name: token.text,
+            line: token.line,
+            kind,
+        });
+        Ok(0)
+    }
+
+    // tokens up to the matching }
+    fn braced(&mut self) -> Result<Vec<Token>, Chip8Error> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match &token.text[..] {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    // substitute the arguments following a macro name into its body
+    fn expand(&mut self, name: &str) -> Result<(), Chip8Error> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(format!(
+                "too many expansions of {}, does it invoke itself?",
+                name
+            )));
+        }
+        let line = self.line;
+        let param_count = self.macros[name].params.len();
+        let mut arguments = Vec::with_capacity(param_count);
+        for _ in 0..param_count {
+            arguments.push(self.next()?.text);
+        }
+        let definition = &self.macros[name];
+        for token in definition.body.iter().rev() {
+            let text = match definition
+                .params
+                .iter()
+                .position(|param| *param == token.text)
+            {
+                Some(index) => arguments[index].clone(),
+                None => token.text.clone(),
+            };
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        self.emit(&encode(instruction).to_be_bytes())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), Chip8Error> {
+        let offset = self.here - PROGRAM_START;
+        if self.here + bytes.len() > MAX_ADDRESS as usize + 1 {
+            return Err(self.error("program doesn't fit into 64k of ram"));
+        }
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, jump: usize, target: usize) -> Result<(), Chip8Error> {
+        let nnn = self.in_range(target as i64, 0xFFF)? as u16;
+        let offset = jump - PROGRAM_START;
+        self.rom[offset..offset + 2]
+            .copy_from_slice(&encode(Instruction::Jump { nnn }).to_be_bytes());
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, Chip8Error> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| self.error("unexpected end of file"))?;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| &token.text[..])
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Chip8Error> {
+        let token = self.next()?;
+        if token.text != expected {
+            return Err(self.error(format!("expected {}, found {}", expected, token.text)));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, Chip8Error> {
+        let token = self.next()?;
+        if !is_identifier(&token.text) {
+            return Err(self.error(format!("invalid name: {}", token.text)));
+        }
+        Ok(token.text)
+    }
+
+    fn define(&self, name: &str) -> Result<(), Chip8Error> {
+        let defined = self.labels.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.aliases.contains_key(name)
+            || self.macros.contains_key(name)
+            || parse_register(name).is_some();
+        if defined {
+            return Err(self.error(format!("{} is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8, Chip8Error> {
+        let token = self.next()?;
+        self.register_named(&token.text)
+            .ok_or_else(|| self.error(format!("expected a register, found {}", token.text)))
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    // number, constant or label defined so far
+    fn symbol(&self, text: &str) -> Option<i64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).map(|value| value.floor() as i64))
+            .or_else(|| self.labels.get(text).map(|&address| address as i64))
+    }
+
+    fn value(&self, text: &str) -> Result<i64, Chip8Error> {
+        self.symbol(text)
+            .ok_or_else(|| self.error(format!("expected a number or constant, found {}", text)))
+    }
+
+    fn byte(&mut self) -> Result<u8, Chip8Error> {
+        let token = self.next()?;
+        self.byte_value(&token.text)
+    }
+
+    fn byte_value(&self, text: &str) -> Result<u8, Chip8Error> {
+        let value = self.value(text)?;
+        self.byte_in_range(value)
+    }
+
+    // negative bytes are two's complement
+    fn byte_in_range(&self, value: i64) -> Result<u8, Chip8Error> {
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit into a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, Chip8Error> {
+        let token = self.next()?;
+        let value = self.value(&token.text)?;
+        Ok(self.in_range(value, 0xF)? as u8)
+    }
+
+    fn in_range(&self, value: i64, max: i64) -> Result<i64, Chip8Error> {
+        if !(0..=max).contains(&value) {
+            return Err(self.error(format!("{:#x} is out of range, at most {:#x}", value, max)));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: impl Into<String>) -> Chip8Error {
+        Chip8Error::Assembly {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+// v0 - vF
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// decimal, 0x hex or 0b binary, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_line(source: &str) -> usize {
+        match assemble(source) {
+            Err(Chip8Error::Assembly { line, .. }) => line,
+            result => panic!("expected an assembly error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sprite_loop() {
+        let source = "
+: main
+  clear
+  v0 := 0x0C
+  i := sprite
+  sprite v0 v1 5
+  loop again
+: sprite
+  0xF0 0x90
+";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x00, 0xE0, 0x60, 0x0C, 0xA2, 0x0A, 0xD0, 0x15, 0x12, 0x08, 0xF0, 0x90]
+        );
+    }
+
+    #[test]
+    fn directives_and_control_flow() {
+        let source = "
+:const SPEED 3
+:alias x v4
+:calc DOUBLE { SPEED * 2 }
+:macro bump REG { REG += SPEED }
+: main
+  x := DOUBLE
+  bump x
+  if x == 9 then x := 0
+  if x != v1 begin
+    v2 := 1
+  else
+    v2 := 2
+  end
+  loop
+    x += 1
+  while x != 20 again
+  :call sub
+: sub
+  return
+";
+        let op_codes: [u16; 16] = [
+            0x1202, // jump main
+            0x6406, 0x7403, 0x4409, 0x6400, 0x9410, 0x1212, 0x6201, 0x1214, 0x6202, 0x7401, 0x4414,
+            0x121C, 0x1214, 0x221E, 0x00EE,
+        ];
+        let expected: Vec<u8> = op_codes.iter().flat_map(|op| op.to_be_bytes()).collect();
+        assert_eq!(assemble(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn errors_name_the_source_line() {
+        assert_eq!(error_line(": main\n  v0 := 1\n  jump nowhere\n"), 3);
+        assert_eq!(
+            error_line(": main\n\n  v0 := 1\n  # comment\n  v0 += 300\n"),
+            5
+        );
+        assert_eq!(error_line(": main\n  v0 := 1\n  end\n"), 3);
+        assert_eq!(error_line(": main\n  if v0 == 1 begin\n    v1 := 2\n"), 2);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::machine::Chip8;
+use crate::timing::FRAME_RATE;
+use std::f32::consts::TAU;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const WAVEFORMS: [&str; 3] = ["square", "sine", "triangle"];
+
+// Shape of the beeper tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+}
+
+/*
+Tone:
+    waveform:
+        - shape of the beeper tone
+    frequency:
+        - pitch in Hz
+    volume:
+        - amplitude from 0.0 (silent) to 1.0 (full scale)
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+}
+
+// What the beeper plays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sound {
+    Silence,
+    // the configured tone
+    Beep,
+    // 128 one bit XO-CHIP samples played at `rate` bits per second
+    Pattern { pattern: [u8; 16], rate: f32 },
+}
+
+/*
+Synth:
+    - generates the beeper samples (signed 16 bit mono)
+    tone:
+        - waveform, frequency and volume of the beep
+    sample_rate:
+        - samples per second
+    phase:
+        - position inside the current period or XO-CHIP pattern (0.0-1.0),
+          kept across calls so consecutive buffers join without clicks
+*/
+pub struct Synth {
+    tone: Tone,
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl Synth {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        Synth {
+            tone,
+            sample_rate,
+            phase: 0f32,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn play(&mut self, sound: Sound, samples: &mut [i16]) {
+        match sound {
+            Sound::Silence => self.render(false, samples),
+            Sound::Beep => self.render(true, samples),
+            Sound::Pattern { pattern, rate } => self.render_pattern(true, &pattern, rate, samples),
+        }
+    }
+
+    // fill `samples` with the beep, or with silence if not `playing`
+    pub fn render(&mut self, playing: bool, samples: &mut [i16]) {
+        if !playing {
+            samples.fill(0);
+            self.phase = 0f32;
+            return;
+        }
+        let step = self.tone.frequency / self.sample_rate as f32;
+        let amplitude = self.tone.volume.clamp(0f32, 1f32) * i16::MAX as f32;
+        for sample in samples.iter_mut() {
+            *sample = (self.tone.waveform.sample(self.phase) * amplitude) as i16;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    // fill `samples` with the 128 one bit XO-CHIP `pattern` played at `rate` bits per second,
+    // or with silence if not `playing`
+    pub fn render_pattern(
+        &mut self,
+        playing: bool,
+        pattern: &[u8; 16],
+        rate: f32,
+        samples: &mut [i16],
+    ) {
+        if !playing {
+            samples.fill(0);
+            self.phase = 0f32;
+            return;
+        }
+        let step = rate / 128f32 / self.sample_rate as f32;
+        let amplitude = self.tone.volume.clamp(0f32, 1f32) * i16::MAX as f32;
+        for sample in samples.iter_mut() {
+            let bit = (self.phase * 128f32) as usize % 128;
+            let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if high { amplitude } else { -amplitude } as i16;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/*
+AudioRecorder:
+    - renders the sound of every emulated frame, e.g. to write it to a wav file
+    synth:
+        - generates the beep
+    samples:
+        - everything rendered so far
+*/
+pub struct AudioRecorder {
+    synth: Synth,
+    samples: Vec<i16>,
+}
+
+impl AudioRecorder {
+    pub fn new(synth: Synth) -> Self {
+        AudioRecorder {
+            synth,
+            samples: vec![],
+        }
+    }
+
+    // render one 60Hz frame of the sound chip8 is currently playing
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        let start = self.samples.len();
+        let frame_samples = (self.synth.sample_rate() / FRAME_RATE) as usize;
+        self.samples.resize(start + frame_samples, 0);
+        chip8.render_audio(&mut self.synth, &mut self.samples[start..]);
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn write_wav(&self, writer: impl Write) -> io::Result<()> {
+        write_wav(writer, self.synth.sample_rate(), &self.samples)
+    }
+}
+
+// 16 bit mono PCM RIFF/WAVE file
+pub fn write_wav(mut writer: impl Write, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_size = samples.len() as u32 * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // channels
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // byte rate
+    writer.write_all(&2u16.to_le_bytes())?; // block align
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+impl Waveform {
+    // value (-1.0-1.0) at `phase` (0.0-1.0) of a period
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1f32
+                } else {
+                    -1f32
+                }
+            }
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1f32 - 4f32 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: 440f32,
+            volume: 0.25,
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(waveform: &str) -> Result<Self, Self::Err> {
+        match waveform {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!(
+                "unknown waveform: {}, expected square, sine or triangle",
+                waveform
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Waveform::Square => write!(f, "square"),
+            Waveform::Sine => write!(f, "sine"),
+            Waveform::Triangle => write!(f, "triangle"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(waveform: Waveform, volume: f32, count: usize) -> Vec<i16> {
+        // 4 samples per period
+        let tone = Tone {
+            waveform,
+            frequency: 11025f32,
+            volume,
+        };
+        let mut synth = Synth::new(tone, SAMPLE_RATE);
+        let mut samples = vec![0; count];
+        synth.render(true, &mut samples);
+        samples
+    }
+
+    #[test]
+    fn square_alternates_between_full_amplitudes() {
+        assert_eq!(
+            render(Waveform::Square, 1f32, 8),
+            [32767, 32767, -32767, -32767, 32767, 32767, -32767, -32767]
+        );
+    }
+
+    #[test]
+    fn sine_follows_the_period() {
+        let samples = render(Waveform::Sine, 1f32, 4);
+        assert_eq!(samples[0], 0);
+        assert_eq!(samples[1], 32767);
+        assert!(samples[2].abs() <= 1);
+        assert_eq!(samples[3], -32767);
+    }
+
+    #[test]
+    fn triangle_ramps_between_the_peaks() {
+        assert_eq!(render(Waveform::Triangle, 1f32, 4), [-32767, 0, 32767, 0]);
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        assert_eq!(
+            render(Waveform::Square, 2f32, 4),
+            render(Waveform::Square, 1f32, 4)
+        );
+        assert_eq!(render(Waveform::Square, -1f32, 4), [0; 4]);
+    }
+
+    #[test]
+    fn silence_when_not_playing() {
+        let mut synth = Synth::new(Tone::default(), SAMPLE_RATE);
+        let mut samples = vec![1; 16];
+        synth.render(false, &mut samples);
+        assert_eq!(samples, [0; 16]);
+    }
+
+    #[test]
+    fn phase_continues_across_renders() {
+        let tone = Tone {
+            waveform: Waveform::Sine,
+            frequency: 440f32,
+            volume: 1f32,
+        };
+        let whole = {
+            let mut synth = Synth::new(tone, SAMPLE_RATE);
+            let mut samples = vec![0; 1000];
+            synth.render(true, &mut samples);
+            samples
+        };
+        let mut synth = Synth::new(tone, SAMPLE_RATE);
+        let mut split = vec![0; 1000];
+        let (first, second) = split.split_at_mut(333);
+        synth.render(true, first);
+        synth.render(true, second);
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn wav_header_describes_16_bit_mono_pcm() {
+        let samples = [0, 1, -1, i16::MAX, i16::MIN];
+        let mut wav = vec![];
+        write_wav(&mut wav, 22050, &samples).unwrap();
+        let u16_at = |at: usize| u16::from_le_bytes([wav[at], wav[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(wav[at..at + 4].try_into().unwrap());
+        assert_eq!(wav.len(), 44 + 2 * samples.len());
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(4), wav.len() as u32 - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(16), 16);
+        assert_eq!(u16_at(20), 1);
+        assert_eq!(u16_at(22), 1);
+        assert_eq!(u32_at(24), 22050);
+        assert_eq!(u32_at(28), 44100);
+        assert_eq!(u16_at(32), 2);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(40), 2 * samples.len() as u32);
+        assert_eq!(u16_at(44 + 2 * 4) as i16, i16::MIN);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::audio::Waveform;
+use crate::database::RomInfo;
+use crate::error::Chip8Error;
+use crate::keymap::is_key_name;
+use crate::mode::Mode;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::rewind::REWIND_SECONDS;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fmt, fs};
+
+/*
+Settings:
+    - one layer of the configuration, None leaves a setting to the layers below
+    - the layers from bottom to top: defaults, user config file, rom database,
+      [roms.<name>] / [roms.<sha1>] sections of the config file, command line
+    - names match the command line flags, in TOML:
+        ipf = 15
+        quirks = "schip-modern"
+        palette = "#000000,#FFAA00"
+        window-size = "1280x640"
+        [hotkeys]
+        rewind = "R"
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    #[serde(with = "parsed")]
+    pub mode: Option<Mode>,
+    pub ipf: Option<u32>,
+    pub vip_timing: Option<bool>,
+    pub quirks: Option<String>,
+    pub shift_quirk: Option<bool>,
+    pub memory_quirk: Option<bool>,
+    pub jump_quirk: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub index_overflow: Option<bool>,
+    #[serde(with = "parsed")]
+    pub palette: Option<Palette>,
+    #[serde(with = "parsed")]
+    pub window_size: Option<WindowSize>,
+    pub scale: Option<u32>,
+    pub keymap: Option<String>,
+    #[serde(with = "parsed")]
+    pub waveform: Option<Waveform>,
+    pub tone: Option<f32>,
+    pub volume: Option<f32>,
+    pub mute: Option<bool>,
+    pub rewind: Option<u32>,
+    #[serde(skip_serializing_if = "Hotkeys::is_empty")]
+    pub hotkeys: Hotkeys,
+}
+
+/*
+Hotkeys:
+    - SFML key names of the emulator functions in the window
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Hotkeys {
+    pub save_state: Option<String>,
+    pub load_state: Option<String>,
+    pub rewind: Option<String>,
+    pub record: Option<String>,
+}
+
+// size of the window in pixels, "800x400"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/*
+ConfigFile:
+    - the user config file, settings for every rom followed by sections for single roms
+    settings:
+        - top level settings
+    roms:
+        - rom file name without extension or sha1 of the rom => settings for that rom
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    pub settings: Settings,
+    roms: BTreeMap<String, Settings>,
+}
+
+impl Settings {
+    // bottom layer, every setting but the quirks (which follow the mode) has a value
+    pub fn defaults() -> Settings {
+        Settings {
+            mode: Some(Mode::Chip8),
+            ipf: Some(12),
+            vip_timing: Some(false),
+            palette: Some(Palette::default()),
+            window_size: Some(WindowSize {
+                width: 800,
+                height: 400,
+            }),
+            scale: Some(8),
+            keymap: Some("qwertz".to_string()),
+            waveform: Some(Waveform::Square),
+            tone: Some(440.0),
+            volume: Some(0.25),
+            mute: Some(false),
+            rewind: Some(REWIND_SECONDS),
+            hotkeys: Hotkeys {
+                save_state: Some("F5".to_string()),
+                load_state: Some("F9".to_string()),
+                rewind: Some("Backspace".to_string()),
+                record: Some("F12".to_string()),
+            },
+            ..Settings::default()
+        }
+    }
+
+    // what the rom database knows about a rom
+    pub fn from_rom_info(info: &RomInfo) -> Settings {
+        let quirks = info.quirks;
+        Settings {
+            mode: Some(info.mode),
+            ipf: Some(info.tickrate),
+            shift_quirk: Some(quirks.shift_uses_vy),
+            memory_quirk: Some(quirks.increment_index),
+            jump_quirk: Some(quirks.jump_uses_vx),
+            vf_reset: Some(quirks.vf_reset),
+            clip_sprites: Some(quirks.clip_sprites),
+            display_wait: Some(quirks.display_wait),
+            index_overflow: Some(quirks.index_overflow),
+            palette: info.palette,
+            ..Settings::default()
+        }
+    }
+
+    // put `layer` on top, a quirk preset replaces the single quirks below it
+    pub fn merge(&mut self, layer: &Settings) {
+        if layer.quirks.is_some() {
+            for quirk in self.single_quirks() {
+                *quirk = None;
+            }
+        }
+        let layer = layer.clone();
+        self.mode = layer.mode.or(self.mode);
+        self.ipf = layer.ipf.or(self.ipf);
+        self.vip_timing = layer.vip_timing.or(self.vip_timing);
+        self.quirks = layer.quirks.or(self.quirks.take());
+        self.shift_quirk = layer.shift_quirk.or(self.shift_quirk);
+        self.memory_quirk = layer.memory_quirk.or(self.memory_quirk);
+        self.jump_quirk = layer.jump_quirk.or(self.jump_quirk);
+        self.vf_reset = layer.vf_reset.or(self.vf_reset);
+        self.clip_sprites = layer.clip_sprites.or(self.clip_sprites);
+        self.display_wait = layer.display_wait.or(self.display_wait);
+        self.index_overflow = layer.index_overflow.or(self.index_overflow);
+        self.palette = layer.palette.or(self.palette);
+        self.window_size = layer.window_size.or(self.window_size);
+        self.scale = layer.scale.or(self.scale);
+        self.keymap = layer.keymap.or(self.keymap.take());
+        self.waveform = layer.waveform.or(self.waveform);
+        self.tone = layer.tone.or(self.tone);
+        self.volume = layer.volume.or(self.volume);
+        self.mute = layer.mute.or(self.mute);
+        self.rewind = layer.rewind.or(self.rewind);
+        let hotkeys = &mut self.hotkeys;
+        hotkeys.save_state = layer.hotkeys.save_state.or(hotkeys.save_state.take());
+        hotkeys.load_state = layer.hotkeys.load_state.or(hotkeys.load_state.take());
+        hotkeys.rewind = layer.hotkeys.rewind.or(hotkeys.rewind.take());
+        hotkeys.record = layer.hotkeys.record.or(hotkeys.record.take());
+    }
+
+    // quirk preset (or the defaults of the mode) with the single quirks applied
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = match &self.quirks {
+            Some(preset) => Quirks::preset(preset).unwrap_or_default(),
+            None => Quirks::for_mode(self.mode.unwrap_or_default()),
+        };
+        let overrides = [
+            (self.shift_quirk, &mut quirks.shift_uses_vy),
+            (self.memory_quirk, &mut quirks.increment_index),
+            (self.jump_quirk, &mut quirks.jump_uses_vx),
+            (self.vf_reset, &mut quirks.vf_reset),
+            (self.clip_sprites, &mut quirks.clip_sprites),
+            (self.display_wait, &mut quirks.display_wait),
+            (self.index_overflow, &mut quirks.index_overflow),
+        ];
+        for (value, quirk) in overrides {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+        quirks
+    }
+
+    // every single quirk filled in from the preset, for printing the effective configuration
+    pub fn resolve_quirks(&mut self) {
+        let quirks = self.quirks();
+        let values = [
+            quirks.shift_uses_vy,
+            quirks.increment_index,
+            quirks.jump_uses_vx,
+            quirks.vf_reset,
+            quirks.clip_sprites,
+            quirks.display_wait,
+            quirks.index_overflow,
+        ];
+        for (quirk, value) in self.single_quirks().into_iter().zip(values) {
+            *quirk = Some(value);
+        }
+    }
+
+    fn single_quirks(&mut self) -> [&mut Option<bool>; 7] {
+        [
+            &mut self.shift_quirk,
+            &mut self.memory_quirk,
+            &mut self.jump_quirk,
+            &mut self.vf_reset,
+            &mut self.clip_sprites,
+            &mut self.display_wait,
+            &mut self.index_overflow,
+        ]
+    }
+
+    // only what changes how the machine runs: mode, speed and every single quirk
+    pub fn machine(&self) -> Settings {
+        let mut resolved = self.clone();
+        resolved.resolve_quirks();
+        Settings {
+            mode: self.mode,
+            ipf: self.ipf,
+            vip_timing: self.vip_timing,
+            shift_quirk: resolved.shift_quirk,
+            memory_quirk: resolved.memory_quirk,
+            jump_quirk: resolved.jump_quirk,
+            vf_reset: resolved.vf_reset,
+            clip_sprites: resolved.clip_sprites,
+            display_wait: resolved.display_wait,
+            index_overflow: resolved.index_overflow,
+            ..Settings::default()
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    // values serde can't check by their type
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(preset) = &self.quirks {
+            if Quirks::preset(preset).is_none() {
+                return Err(format!("unknown quirk preset: {}", preset));
+            }
+        }
+        if self.ipf == Some(0) {
+            return Err("ipf has to be at least 1".to_string());
+        }
+        if self.scale == Some(0) {
+            return Err("scale has to be at least 1".to_string());
+        }
+        let hotkeys = [
+            &self.hotkeys.save_state,
+            &self.hotkeys.load_state,
+            &self.hotkeys.rewind,
+            &self.hotkeys.record,
+        ];
+        for name in hotkeys.into_iter().flatten() {
+            if !is_key_name(name) {
+                return Err(format!("unknown key name: {}", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Hotkeys {
+    pub fn is_empty(&self) -> bool {
+        *self == Hotkeys::default()
+    }
+}
+
+impl ConfigFile {
+    // $XDG_CONFIG_HOME/chip8/config.toml, ~/.config/chip8/config.toml or %APPDATA%\chip8\config.toml
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(dir.join("chip8").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<ConfigFile, Chip8Error> {
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| ConfigFile::from_toml(&text))
+            .map_err(|message| Chip8Error::Config {
+                message: format!("{}: {}", path.display(), message),
+            })
+    }
+
+    pub fn from_toml(text: &str) -> Result<ConfigFile, String> {
+        let mut table: toml::Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| err.to_string())?;
+        let roms = match table.remove("roms") {
+            Some(toml::Value::Table(roms)) => roms,
+            Some(_) => return Err("roms has to be a table of rom sections".to_string()),
+            None => toml::Table::new(),
+        };
+        let settings = settings_from_table(table)?;
+        let roms = roms
+            .into_iter()
+            .map(|(rom, section)| {
+                let settings = match section {
+                    toml::Value::Table(section) => settings_from_table(section),
+                    _ => Err("has to be a table".to_string()),
+                };
+                settings
+                    .map(|settings| (rom.clone(), settings))
+                    .map_err(|message| format!("in [roms.{}]: {}", rom, message))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ConfigFile { settings, roms })
+    }
+
+    // sections of a rom, the one for its file name (without extension) before the one for its sha1
+    pub fn rom_sections(&self, name: &str, sha1: &str) -> Vec<(&str, &Settings)> {
+        [name, sha1]
+            .into_iter()
+            .filter_map(|key| {
+                self.roms
+                    .iter()
+                    .find(|(rom, _)| rom.eq_ignore_ascii_case(key))
+            })
+            .map(|(rom, settings)| (rom.as_str(), settings))
+            .collect()
+    }
+}
+
+fn settings_from_table(table: toml::Table) -> Result<Settings, String> {
+    let settings: Settings = toml::Value::Table(table)
+        .try_into()
+        .map_err(|err: toml::de::Error| err.message().to_string())?;
+    settings.check()?;
+    Ok(settings)
+}
+
+impl FromStr for WindowSize {
+    type Err = String;
+
+    fn from_str(size: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid window size: {}, expected WIDTHxHEIGHT", size);
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+        let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(WindowSize { width, height })
+    }
+}
+
+impl fmt::Display for WindowSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+// settings written as the string of their FromStr / Display implementations
+mod parsed {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map(Some).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(layers: &[&Settings]) -> Settings {
+        let mut settings = Settings::default();
+        for layer in layers {
+            settings.merge(layer);
+        }
+        settings
+    }
+
+    #[test]
+    fn higher_layers_win() {
+        let config = ConfigFile::from_toml(
+            r#"
+                ipf = 20
+                scale = 4
+                clip-sprites = false
+                [roms.pong]
+                ipf = 40
+            "#,
+        )
+        .unwrap();
+        let rom_info = RomInfo {
+            title: "Pong".to_string(),
+            platform: "originalChip8".to_string(),
+            mode: Mode::Chip8,
+            quirks: Quirks::preset("cosmac-vip").unwrap(),
+            tickrate: 30,
+            palette: None,
+            keys: vec![],
+        };
+        let sections = config.rom_sections("PONG", "unknown sha1");
+        assert_eq!(sections.len(), 1);
+        let command_line = Settings {
+            ipf: Some(50),
+            quirks: Some("chip48".to_string()),
+            ..Settings::default()
+        };
+        let movie = Settings {
+            ipf: Some(60),
+            ..Settings::default()
+        };
+
+        let defaults = Settings::defaults();
+        let database = Settings::from_rom_info(&rom_info);
+        let layers = [
+            &defaults,
+            &config.settings,
+            &database,
+            sections[0].1,
+            &command_line,
+            &movie,
+        ];
+        let ipfs: Vec<Option<u32>> = (1..=layers.len())
+            .map(|top| merged(&layers[..top]).ipf)
+            .collect();
+        let expected = [12, 20, 30, 40, 50, 60];
+        assert_eq!(ipfs, expected.map(Some));
+
+        // the config file's quirk is overridden by the database, whose quirks are
+        // replaced by the preset given on the command line
+        assert!(!merged(&layers[..2]).quirks().clip_sprites);
+        assert_eq!(merged(&layers[..3]).quirks(), rom_info.quirks);
+        let settings = merged(&layers);
+        assert_eq!(settings.quirks(), Quirks::preset("chip48").unwrap());
+        // settings no layer above sets are kept
+        assert_eq!(settings.scale, Some(4));
+        assert_eq!(settings.keymap.as_deref(), Some("qwertz"));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::mode::Mode;
+use crate::palette::{parse_hex_color, Palette};
+use crate::quirks::Quirks;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fs, io};
+
+/*
+Database:
+    - ROM metadata in the format of the community CHIP-8 database
+      (programs.json, sha1-hashes.json, platforms.json)
+    programs:
+        - every known program with its ROM variants keyed by sha1
+    hashes:
+        - sha1 of a ROM => index into programs
+    platforms:
+        - default quirks and tick rate of each platform id
+*/
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    platforms: Vec<Platform>,
+}
+
+// Settings detected for a known ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub platform: String,
+    pub mode: Mode,
+    pub quirks: Quirks,
+    pub tickrate: u32,
+    pub palette: Option<Palette>,
+    pub keys: Vec<(String, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, PlatformQuirks>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    default_tickrate: u32,
+    quirks: PlatformQuirks,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct PlatformQuirks {
+    shift: bool,
+    memory_increment_by_x: bool,
+    memory_leave_i_unchanged: bool,
+    wrap: bool,
+    jump: bool,
+    vblank: bool,
+    logic: bool,
+}
+
+impl Database {
+    // the copy committed in database/, so builds don't depend on the network
+    pub fn bundled() -> Self {
+        Self::parse(
+            include_str!("../database/programs.json"),
+            include_str!("../database/sha1-hashes.json"),
+            include_str!("../database/platforms.json"),
+        )
+        .expect("bundled rom database is invalid")
+    }
+
+    // a directory containing programs.json, sha1-hashes.json and platforms.json
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        Self::parse(
+            &fs::read_to_string(dir.join("programs.json"))?,
+            &fs::read_to_string(dir.join("sha1-hashes.json"))?,
+            &fs::read_to_string(dir.join("platforms.json"))?,
+        )
+    }
+
+    fn parse(programs: &str, hashes: &str, platforms: &str) -> io::Result<Self> {
+        Ok(Database {
+            programs: serde_json::from_str(programs)?,
+            hashes: serde_json::from_str(hashes)?,
+            platforms: serde_json::from_str(platforms)?,
+        })
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = sha1(rom);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let rom = program.roms.get(&hash)?;
+        let (platform, mode) = rom
+            .platforms
+            .iter()
+            .find_map(|id| Some((self.platform(id)?, platform_mode(id)?)))?;
+        let quirks = rom
+            .quirky_platforms
+            .get(&platform.id)
+            .unwrap_or(&platform.quirks);
+        let mut keys: Vec<(String, u8)> = rom
+            .keys
+            .iter()
+            .map(|(name, key)| (name.clone(), *key))
+            .collect();
+        keys.sort();
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            platform: platform.id.clone(),
+            mode,
+            quirks: quirks.to_quirks(),
+            tickrate: rom.tickrate.unwrap_or(platform.default_tickrate),
+            palette: rom.colors.as_ref().and_then(|colors| colors.to_palette()),
+            keys,
+        })
+    }
+
+    fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+}
+
+pub fn sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+fn platform_mode(id: &str) -> Option<Mode> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" | "chip48" => Some(Mode::Chip8),
+        "superchip1" | "superchip" => Some(Mode::SuperChip),
+        "xochip" => Some(Mode::XoChip),
+        _ => None,
+    }
+}
+
+impl PlatformQuirks {
+    // memoryIncrementByX has no equivalent, such platforms increment by x + 1
+    fn to_quirks(self) -> Quirks {
+        Quirks {
+            shift_uses_vy: !self.shift,
+            increment_index: !self.memory_leave_i_unchanged,
+            jump_uses_vx: self.jump,
+            vf_reset: self.logic,
+            clip_sprites: !self.wrap,
+            display_wait: self.vblank,
+            // no platform of the database sets var[F] on FX1E
+            index_overflow: false,
+        }
+    }
+}
+
+impl Colors {
+    fn to_palette(&self) -> Option<Palette> {
+        if self.pixels.is_empty() {
+            return None;
+        }
+        let mut palette = Palette::default();
+        for (color, hex) in palette.colors.iter_mut().zip(self.pixels.iter()) {
+            *color = parse_hex_color(hex)?;
+        }
+        Some(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_match_their_platforms() {
+        let database = Database::bundled();
+        for (preset, id) in [
+            ("cosmac-vip", "originalChip8"),
+            ("chip48", "chip48"),
+            ("schip-modern", "superchip"),
+            ("xo-chip", "xochip"),
+        ] {
+            let platform = database.platform(id).unwrap();
+            assert_eq!(
+                Quirks::preset(preset),
+                Some(platform.quirks.to_quirks()),
+                "{}",
+                preset
+            );
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use chip8::debugger::{op_code_at, parse_address, Breakpoint, Debugger, Stop, Watchpoint};
+use chip8::instruction::decode;
+use chip8::Chip8;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// instructions shown before and after pc
+const CONTEXT: u16 = 4;
+
+const HELP: &str = "\
+c, continue          run until a breakpoint is hit
+s, step [N]          execute N instructions (default 1)
+n, next              step over 2NNN calls
+o, out               run until the current subroutine returns
+u, until ADDR        run to ADDR
+b, break ADDR|OP     break at an address (0x2A0) or opcode pattern (DXYN, 00E0)
+d, delete [N]        delete breakpoint N, or all of them
+w, watch WATCH       stop after an instruction touches: ram ADDR[-ADDR] [r|w|rw],
+                     vX[=VALUE] (register changes), i ADDR[-ADDR] (I moves into range)
+unwatch [N]          delete watchpoint N, or all of them
+i, info              list breakpoints and watchpoints
+r, regs              show registers, stack and disassembly
+p, pause             stop execution
+q, quit              exit the emulator";
+
+/*
+DebugConsole:
+    - command line of the debugger on stdin/stdout, the window keeps running meanwhile
+    commands:
+        - lines read from stdin by a background thread
+    quit_flag:
+        - quit command or end of input
+*/
+pub struct DebugConsole {
+    commands: Receiver<String>,
+    quit_flag: bool,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        DebugConsole {
+            commands,
+            quit_flag: false,
+        }
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit_flag
+    }
+
+    // report the last stop and execute the commands typed since the last update
+    pub fn update(&mut self, debugger: &mut Debugger, chip8: &mut Chip8) {
+        if let Some(stop) = debugger.take_stop() {
+            report_stop(stop, debugger, chip8);
+            prompt();
+        }
+        loop {
+            match self.commands.try_recv() {
+                Ok(line) => {
+                    self.execute(&line, debugger, chip8);
+                    if debugger.paused() {
+                        prompt();
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.quit_flag = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, line: &str, debugger: &mut Debugger, chip8: &mut Chip8) {
+        let mut words = line.split_whitespace();
+        let (command, argument) = (words.next().unwrap_or_default(), words.next());
+        let rest = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .map(|(_, rest)| rest);
+        match (command, argument) {
+            ("", _) => {}
+            ("c" | "continue", _) => debugger.resume(),
+            ("s" | "step", None) => debugger.step(1),
+            ("s" | "step", Some(count)) => match count.parse() {
+                Ok(count) => debugger.step(count),
+                Err(_) => println!("invalid instruction count: {}", count),
+            },
+            ("n" | "next", _) => debugger.step_over(chip8),
+            ("o" | "out", _) => debugger.step_out(chip8),
+            ("u" | "until", Some(address)) => match parse_address(address) {
+                Ok(address) => debugger.run_to(address),
+                Err(err) => println!("{}", err),
+            },
+            ("b" | "break", Some(breakpoint)) => match breakpoint.parse::<Breakpoint>() {
+                Ok(breakpoint) => {
+                    let index = debugger.add_breakpoint(breakpoint.clone());
+                    println!("breakpoint {}: {}", index, breakpoint)
+                }
+                Err(err) => println!("{}", err),
+            },
+            ("d" | "delete", None) => debugger.clear_breakpoints(),
+            ("d" | "delete", Some(index)) => {
+                match index
+                    .parse()
+                    .ok()
+                    .and_then(|index| debugger.remove_breakpoint(index))
+                {
+                    Some(breakpoint) => println!("deleted {}", breakpoint),
+                    None => println!("no breakpoint {}", index),
+                }
+            }
+            ("w" | "watch", Some(_)) => match rest.unwrap_or_default().parse::<Watchpoint>() {
+                Ok(watchpoint) => {
+                    let index = debugger.add_watchpoint(chip8, watchpoint);
+                    println!("watchpoint {}: {}", index, watchpoint)
+                }
+                Err(err) => println!("{}", err),
+            },
+            ("unwatch", None) => debugger.clear_watchpoints(),
+            ("unwatch", Some(index)) => {
+                match index
+                    .parse()
+                    .ok()
+                    .and_then(|index| debugger.remove_watchpoint(index))
+                {
+                    Some(watchpoint) => println!("deleted {}", watchpoint),
+                    None => println!("no watchpoint {}", index),
+                }
+            }
+            ("i" | "info", _) => {
+                for (index, breakpoint) in debugger.breakpoints().iter().enumerate() {
+                    println!("breakpoint {}: {}", index, breakpoint);
+                }
+                for (index, watchpoint) in debugger.watchpoints().iter().enumerate() {
+                    println!("watchpoint {}: {}", index, watchpoint);
+                }
+            }
+            ("r" | "regs", _) => print_state(debugger, chip8),
+            ("p" | "pause", _) => debugger.pause(),
+            ("q" | "quit", _) => self.quit_flag = true,
+            ("h" | "help", _) => println!("{}", HELP),
+            _ => println!(
+                "unknown command: {}, type help for a list of commands",
+                line.trim()
+            ),
+        }
+    }
+}
+
+fn report_stop(stop: Stop, debugger: &Debugger, chip8: &Chip8) {
+    match stop {
+        Stop::Breakpoint(index) => println!(
+            "hit breakpoint {}: {}",
+            index,
+            debugger.breakpoints()[index]
+        ),
+        Stop::Watchpoint { index, pc } => println!(
+            "watchpoint {}: {} triggered by the instruction at {:#05x}",
+            index,
+            debugger.watchpoints()[index],
+            pc
+        ),
+        Stop::Fault => println!("stopped at faulting instruction"),
+        Stop::Paused | Stop::Step => {}
+    }
+    print_state(debugger, chip8);
+}
+
+fn print_state(debugger: &Debugger, chip8: &Chip8) {
+    let memory = chip8.memory();
+    println!(
+        "pc {:#05x}  I {:#05x}  delay {}  sound {}",
+        memory.pc(),
+        memory.index_register(),
+        memory.delay_register(),
+        memory.sound_register()
+    );
+    for (row, values) in memory.var_registers().chunks(8).enumerate() {
+        let registers: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X} {:02x}", row * 8 + i, value))
+            .collect();
+        println!("{}", registers.join("  "));
+    }
+    let stack: Vec<String> = memory
+        .stack()
+        .iter()
+        .map(|address| format!("{:#05x}", address))
+        .collect();
+    println!("stack [{}]", stack.join(", "));
+
+    let pc = memory.pc();
+    let start = pc.saturating_sub(CONTEXT * 2);
+    for address in (start..=pc.saturating_add(CONTEXT * 2)).step_by(2) {
+        let Some(op_code) = op_code_at(memory, address) else {
+            break;
+        };
+        let marker = if address == pc { '>' } else { ' ' };
+        let breakpoint = debugger
+            .breakpoints()
+            .contains(&Breakpoint::Address(address));
+        let mnemonic = match decode(op_code) {
+            Ok(instruction) if instruction.supported_by(chip8.mode()) => {
+                instruction.mnemonic(chip8.quirks())
+            }
+            _ => "???".to_string(),
+        };
+        println!(
+            "{}{} {:#05x}  {:04X}  {}",
+            marker,
+            if breakpoint { '*' } else { ' ' },
+            address,
+            op_code,
+            mnemonic
+        );
+    }
+}
+
+fn prompt() {
+    print!("(debug) ");
+    io::stdout().flush().ok();
+}
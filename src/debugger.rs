@@ -0,0 +1,565 @@
+use crate::instruction::{decode, Instruction};
+use crate::machine::Chip8;
+use crate::memory::{Memory, RamAccess};
+use std::fmt;
+use std::str::FromStr;
+
+/*
+OpcodePattern:
+    - four nibbles, hex digits must match, X, Y, N and K match anything (e.g. DXYN, 8XY4, 00E0)
+    value:
+        - the fixed nibbles
+    mask:
+        - 0xF for every fixed nibble
+    text:
+        - the pattern as written
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodePattern {
+    value: u16,
+    mask: u16,
+    text: String,
+}
+
+// Where the debugger stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    Address(u16),
+    Opcode(OpcodePattern),
+}
+
+/*
+Watchpoint:
+    - stops after the instruction that touched the watched state
+    Ram:
+        - a cell in start..=end is read and/or written
+    Register:
+        - var[x] changes, or is written with `value` (even if it held it already)
+    Index:
+        - I changes to an address in start..=end
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    Ram {
+        start: u16,
+        end: u16,
+        read: bool,
+        write: bool,
+    },
+    Register {
+        x: u8,
+        value: Option<u8>,
+    },
+    Index {
+        start: u16,
+        end: u16,
+    },
+}
+
+// Why the debugger stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Paused,
+    Breakpoint(usize),
+    // the instruction at pc triggered watchpoint n
+    Watchpoint { index: usize, pc: u16 },
+    Step,
+    Fault,
+}
+
+// State watchpoints compare against, taken before every instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    pc: u16,
+    var_registers: [u8; 16],
+    index: u16,
+}
+
+// What has to happen before the debugger stops again, besides hitting a breakpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    // after n more instructions
+    Steps(u32),
+    // the call at `address - 2` returned
+    Return { address: u16, depth: usize },
+    // the current subroutine returned
+    Out { depth: usize },
+    Cursor(u16),
+}
+
+/*
+Debugger:
+    - decides before every instruction whether execution stops
+    breakpoints:
+        - addresses and opcode patterns to stop at
+    watchpoints:
+        - ram, register and I conditions to stop at
+    snapshot:
+        - registers before the last executed instruction
+    paused:
+        - no instruction runs until resumed
+    target:
+        - step, step over, step out or run to cursor in progress
+    resumed:
+        - execution continues from a breakpoint, the breakpoint doesn't stop it right away
+    stop:
+        - reason of the last stop, until taken by the frontend
+*/
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    snapshot: Option<Snapshot>,
+    paused: bool,
+    target: Option<Target>,
+    resumed: bool,
+    stop: Option<Stop>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        (index < self.breakpoints.len()).then(|| self.breakpoints.remove(index))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear()
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // logging starts right away, so the next instruction is already watched
+    pub fn add_watchpoint(&mut self, chip8: &mut Chip8, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        if watchpoint.needs_access_log() {
+            chip8.memory_mut().set_access_logging(true);
+        }
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.halt(Stop::Paused)
+    }
+
+    // stop because the instruction at pc faulted
+    pub fn fault(&mut self) {
+        self.halt(Stop::Fault)
+    }
+
+    pub fn take_stop(&mut self) -> Option<Stop> {
+        self.stop.take()
+    }
+
+    pub fn resume(&mut self) {
+        self.run(None)
+    }
+
+    pub fn step(&mut self, instructions: u32) {
+        self.run(Some(Target::Steps(instructions)))
+    }
+
+    // like step, but a 2NNN call runs until it returns
+    pub fn step_over(&mut self, chip8: &Chip8) {
+        let memory = chip8.memory();
+        match op_code_at(memory, memory.pc()).map(decode) {
+            Some(Ok(Instruction::Call { .. })) => self.run(Some(Target::Return {
+                address: memory.pc().wrapping_add(2),
+                depth: memory.stack().len(),
+            })),
+            _ => self.step(1),
+        }
+    }
+
+    // run until the current subroutine returns with 00EE
+    pub fn step_out(&mut self, chip8: &Chip8) {
+        self.run(Some(Target::Out {
+            depth: chip8.memory().stack().len(),
+        }))
+    }
+
+    pub fn run_to(&mut self, address: u16) {
+        self.run(Some(Target::Cursor(address)))
+    }
+
+    // whether execution stops before the next instruction of chip8
+    pub fn should_break(&mut self, chip8: &mut Chip8) -> bool {
+        if self.paused {
+            return true;
+        }
+        if chip8.waiting() {
+            return false;
+        }
+        let accesses = chip8.memory_mut().take_access_log();
+        let written = chip8.memory_mut().take_register_log();
+        // stop logging once the last watchpoint needing it is deleted
+        if !self.watchpoints.iter().any(Watchpoint::needs_access_log) {
+            chip8.memory_mut().set_access_logging(false);
+        }
+
+        let memory = chip8.memory();
+        let snapshot = Snapshot {
+            pc: memory.pc(),
+            var_registers: *memory.var_registers(),
+            index: memory.index_register(),
+        };
+        if let Some(previous) = self.snapshot.replace(snapshot) {
+            let triggered = self.watchpoints.iter().position(|watchpoint| {
+                watchpoint.triggered(&previous, &snapshot, &accesses, written)
+            });
+            if let Some(index) = triggered {
+                self.halt(Stop::Watchpoint {
+                    index,
+                    pc: previous.pc,
+                });
+                return true;
+            }
+        }
+
+        let pc = memory.pc();
+        let depth = memory.stack().len();
+        let reached = match self.target {
+            Some(Target::Steps(instructions)) => instructions == 0,
+            Some(Target::Return {
+                address,
+                depth: call_depth,
+            }) => pc == address && depth <= call_depth,
+            Some(Target::Out { depth: call_depth }) => depth < call_depth,
+            Some(Target::Cursor(address)) => pc == address,
+            None => false,
+        };
+        let breakpoint = if std::mem::take(&mut self.resumed) {
+            None
+        } else {
+            self.breakpoint_at(memory)
+        };
+
+        if let Some(index) = breakpoint {
+            self.halt(Stop::Breakpoint(index));
+        } else if reached {
+            self.halt(Stop::Step);
+        } else if let Some(Target::Steps(instructions)) = &mut self.target {
+            *instructions -= 1;
+        }
+        self.paused
+    }
+
+    // index of the first breakpoint matching the instruction at pc
+    pub fn breakpoint_at(&self, memory: &Memory) -> Option<usize> {
+        let pc = memory.pc();
+        let op_code = op_code_at(memory, pc);
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| match breakpoint {
+                Breakpoint::Address(address) => *address == pc,
+                Breakpoint::Opcode(pattern) => op_code.is_some_and(|op| pattern.matches(op)),
+            })
+    }
+
+    fn run(&mut self, target: Option<Target>) {
+        self.paused = false;
+        self.resumed = true;
+        self.target = target;
+    }
+
+    fn halt(&mut self, stop: Stop) {
+        self.paused = true;
+        self.target = None;
+        self.stop = Some(stop);
+    }
+}
+
+impl Watchpoint {
+    fn needs_access_log(&self) -> bool {
+        matches!(self, Watchpoint::Ram { .. } | Watchpoint::Register { .. })
+    }
+
+    // `written` has bit x set if the instruction wrote VX
+    fn triggered(
+        &self,
+        before: &Snapshot,
+        after: &Snapshot,
+        accesses: &[RamAccess],
+        written: u16,
+    ) -> bool {
+        match *self {
+            Watchpoint::Ram {
+                start,
+                end,
+                read,
+                write,
+            } => accesses.iter().any(|access| {
+                (start..=end).contains(&access.address) && if access.write { write } else { read }
+            }),
+            Watchpoint::Register { x, value } => {
+                let (old, new) = (
+                    before.var_registers[x as usize],
+                    after.var_registers[x as usize],
+                );
+                match value {
+                    // writing the value it already holds counts as well
+                    Some(value) => written & (1 << x) != 0 && new == value,
+                    None => old != new,
+                }
+            }
+            Watchpoint::Index { start, end } => {
+                before.index != after.index && (start..=end).contains(&after.index)
+            }
+        }
+    }
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, op_code: u16) -> bool {
+        op_code & self.mask == self.value
+    }
+}
+
+pub fn op_code_at(memory: &Memory, address: u16) -> Option<u16> {
+    let high = memory.read_ram_cell(address).ok()?;
+    let low = memory.read_ram_cell(address.wrapping_add(1)).ok()?;
+    Some((high as u16) << 8 | low as u16)
+}
+
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let text = pattern.to_uppercase();
+        if text.chars().count() != 4 {
+            return Err(format!("opcode pattern must have 4 nibbles: {}", pattern));
+        }
+        let (mut value, mut mask) = (0u16, 0u16);
+        for nibble in text.chars() {
+            value <<= 4;
+            mask <<= 4;
+            match nibble {
+                'X' | 'Y' | 'N' | 'K' => {}
+                _ => {
+                    let digit = nibble
+                        .to_digit(16)
+                        .ok_or_else(|| format!("invalid opcode pattern: {}", pattern))?;
+                    value |= digit as u16;
+                    mask |= 0xF;
+                }
+            }
+        }
+        Ok(OpcodePattern { value, mask, text })
+    }
+}
+
+// 0x prefixed or shorter than 4 digits: address, otherwise opcode pattern
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(breakpoint: &str) -> Result<Self, Self::Err> {
+        match breakpoint.strip_prefix("0x") {
+            Some(address) => parse_address(address).map(Breakpoint::Address),
+            None if breakpoint.len() < 4 => parse_address(breakpoint).map(Breakpoint::Address),
+            None => breakpoint.parse().map(Breakpoint::Opcode),
+        }
+    }
+}
+
+/*
+Watchpoint syntax:
+    ram ADDR[-ADDR] [r|w|rw]
+    vX[=VALUE]
+    i ADDR[-ADDR]
+*/
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(watchpoint: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = watchpoint.split_whitespace().collect();
+        let lower = words
+            .first()
+            .map(|word| word.to_lowercase())
+            .unwrap_or_default();
+        match (lower.as_str(), &words[1..]) {
+            ("ram", [range]) | ("ram", [range, _]) => {
+                let (start, end) = parse_range(range)?;
+                let (read, write) = match words.get(2).copied() {
+                    None | Some("rw") => (true, true),
+                    Some("r") => (true, false),
+                    Some("w") => (false, true),
+                    Some(access) => return Err(format!("invalid access, expected r, w or rw: {}", access)),
+                };
+                Ok(Watchpoint::Ram {
+                    start,
+                    end,
+                    read,
+                    write,
+                })
+            }
+            ("i", [range]) => {
+                let (start, end) = parse_range(range)?;
+                Ok(Watchpoint::Index { start, end })
+            }
+            (register, []) if register.starts_with('v') => {
+                let (register, value) = match register.split_once('=') {
+                    Some((register, value)) => (register, Some(parse_value(value)?)),
+                    None => (register, None),
+                };
+                match u8::from_str_radix(&register[1..], 16) {
+                    Ok(x) if x <= 0xF => Ok(Watchpoint::Register { x, value }),
+                    _ => Err(format!("invalid register: {}", register)),
+                }
+            }
+            _ => Err(format!(
+                "invalid watchpoint: {}, expected ram ADDR[-ADDR] [r|w|rw], vX[=VALUE] or i ADDR[-ADDR]",
+                watchpoint
+            )),
+        }
+    }
+}
+
+// ADDR or ADDR-ADDR
+fn parse_range(range: &str) -> Result<(u16, u16), String> {
+    match range.split_once('-') {
+        Some((start, end)) => Ok((parse_address(start)?, parse_address(end)?)),
+        None => parse_address(range).map(|address| (address, address)),
+    }
+}
+
+// decimal or 0x prefixed hex byte
+fn parse_value(value: &str) -> Result<u8, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("invalid value: {}", value))
+}
+
+// hex address with an optional 0x prefix
+pub fn parse_address(address: &str) -> Result<u16, String> {
+    let digits = address.strip_prefix("0x").unwrap_or(address);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address: {}", address))
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Watchpoint::Ram {
+                start,
+                end,
+                read,
+                write,
+            } => {
+                let access = match (read, write) {
+                    (true, false) => "r",
+                    (false, true) => "w",
+                    _ => "rw",
+                };
+                write!(f, "ram {:#05x}-{:#05x} {}", start, end, access)
+            }
+            Watchpoint::Register { x, value: None } => write!(f, "v{:X}", x),
+            Watchpoint::Register {
+                x,
+                value: Some(value),
+            } => write!(f, "v{:X}={}", x, value),
+            Watchpoint::Index { start, end } => write!(f, "i {:#05x}-{:#05x}", start, end),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Address(address) => write!(f, "{:#05x}", address),
+            Breakpoint::Opcode(pattern) => write!(f, "opcode {}", pattern),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Mode, Quirks};
+
+    // run until the debugger stops or the program counter reaches `end`
+    fn run_until_stop(debugger: &mut Debugger, chip8: &mut Chip8, end: u16) -> Option<Stop> {
+        debugger.resume();
+        while chip8.memory().pc() != end {
+            if debugger.should_break(chip8) {
+                return debugger.take_stop();
+            }
+            chip8.step().unwrap();
+        }
+        None
+    }
+
+    fn chip8(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new(Mode::Chip8, Quirks::default());
+        chip8.load_rom_bytes(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn register_watch_fires_when_written_with_the_value_it_holds() {
+        // v1 := 5, v1 := 5, v2 := 5, v1 := 6
+        let mut chip8 = chip8(&[0x61, 0x05, 0x61, 0x05, 0x62, 0x05, 0x61, 0x06]);
+        let mut debugger = Debugger::new();
+        let watchpoint = "v1=5".parse().unwrap();
+        debugger.add_watchpoint(&mut chip8, watchpoint);
+        let mut stops = vec![];
+        while let Some(stop) = run_until_stop(&mut debugger, &mut chip8, 0x208) {
+            stops.push(stop);
+        }
+        let stop = |pc| Stop::Watchpoint { index: 0, pc };
+        assert_eq!(stops, [stop(0x200), stop(0x202)]);
+    }
+
+    #[test]
+    fn ram_watch_sees_the_next_instruction() {
+        // i := 0x300, load v0
+        let mut chip8 = chip8(&[0xA3, 0x00, 0xF0, 0x65]);
+        let mut debugger = Debugger::new();
+        assert!(!debugger.should_break(&mut chip8));
+        chip8.step().unwrap();
+        assert!(!debugger.should_break(&mut chip8));
+        // added between two instructions, the load right after must trigger it
+        let watchpoint = "ram 0x300 r".parse().unwrap();
+        debugger.add_watchpoint(&mut chip8, watchpoint);
+        chip8.step().unwrap();
+        assert!(debugger.should_break(&mut chip8));
+        assert_eq!(
+            debugger.take_stop(),
+            Some(Stop::Watchpoint {
+                index: 0,
+                pc: 0x202
+            })
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use crate::instruction::{decode, Instruction};
+use crate::mode::Mode;
+use crate::quirks::Quirks;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+const PROGRAM_START: usize = 0x200;
+// data bytes per line
+const DATA_ROW: usize = 8;
+
+// Assembly language the mnemonics are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    #[default]
+    Octo,
+    Cowgod,
+}
+
+// What a label marks, a subroutine wins over a jump target wins over data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+/*
+Disassembly:
+    - a rom split into code and data
+    rom:
+        - the program, loaded at 0x200
+    mode:
+        - instruction set used to decode the program
+    quirks:
+        - BNNN is printed as JP VX, XNN with the jump quirk (Cowgod syntax, Octo's jump0 is both)
+    code:
+        - length of the instruction starting at each rom byte, 0 for data
+    labels:
+        - jump, call and I targets inside the rom
+*/
+pub struct Disassembly<'a> {
+    rom: &'a [u8],
+    mode: Mode,
+    quirks: Quirks,
+    code: Vec<u8>,
+    labels: BTreeMap<usize, LabelKind>,
+}
+
+impl<'a> Disassembly<'a> {
+    // recursive descent from 0x200, following jumps, calls and both sides of skips
+    pub fn new(rom: &'a [u8], mode: Mode, quirks: Quirks) -> Self {
+        let mut disassembly = Disassembly {
+            rom,
+            mode,
+            quirks,
+            code: vec![0; rom.len()],
+            labels: BTreeMap::new(),
+        };
+        let mut pending = vec![PROGRAM_START];
+        while let Some(address) = pending.pop() {
+            disassembly.trace(address, &mut pending);
+        }
+        disassembly.drop_hidden_labels();
+        disassembly
+    }
+
+    // a target inside an instruction (odd, or in the second word of F000 NNNN) can't get a
+    // label line, it is printed as a number instead, data rows are split at every label
+    fn drop_hidden_labels(&mut self) {
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let length = (self.code[offset] as usize).max(1);
+            for inside in offset + 1..offset + length {
+                self.labels.remove(&(PROGRAM_START + inside));
+            }
+            offset += length;
+        }
+    }
+
+    // decode linearly from `address` until control flow leaves or known code is reached
+    fn trace(&mut self, mut address: usize, pending: &mut Vec<usize>) {
+        while let Some(instruction) = self.instruction_at(address) {
+            let offset = address - PROGRAM_START;
+            if self.code[offset] != 0 {
+                return;
+            }
+            let length = self.length_of(instruction);
+            if offset + length > self.rom.len() {
+                return;
+            }
+            self.code[offset] = length as u8;
+            let next = address + length;
+            match instruction {
+                Instruction::Jump { nnn } => {
+                    self.add_label(nnn as usize, LabelKind::Jump);
+                    pending.push(nnn as usize);
+                    return;
+                }
+                Instruction::Call { nnn } => {
+                    self.add_label(nnn as usize, LabelKind::Subroutine);
+                    pending.push(nnn as usize);
+                }
+                Instruction::SetIndex { nnn } => self.add_label(nnn as usize, LabelKind::Data),
+                Instruction::SkipIfEqImm { .. }
+                | Instruction::SkipIfNeqImm { .. }
+                | Instruction::SkipIfEq { .. }
+                | Instruction::SkipIfNeq { .. }
+                | Instruction::SkipIfKey { .. }
+                | Instruction::SkipIfNotKey { .. } => {
+                    if let Some(skipped) = self.instruction_at(next) {
+                        pending.push(next + self.length_of(skipped));
+                    }
+                }
+                // BNNN targets depend on a register
+                Instruction::Return | Instruction::Exit | Instruction::JumpWithOffset { .. } => {
+                    return
+                }
+                _ => {}
+            }
+            address = next;
+        }
+    }
+
+    fn instruction_at(&self, address: usize) -> Option<Instruction> {
+        let op_code = self.word_at(address)?;
+        decode(op_code)
+            .ok()
+            .filter(|instruction| instruction.supported_by(self.mode))
+    }
+
+    fn word_at(&self, address: usize) -> Option<u16> {
+        let offset = address.checked_sub(PROGRAM_START)?;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    // F000 NNNN takes the following word as well
+    fn length_of(&self, instruction: Instruction) -> usize {
+        match instruction {
+            Instruction::LoadLongIndex => 4,
+            _ => 2,
+        }
+    }
+
+    fn add_label(&mut self, address: usize, kind: LabelKind) {
+        if (PROGRAM_START..PROGRAM_START + self.rom.len()).contains(&address) {
+            let label = self.labels.entry(address).or_insert(kind);
+            *label = (*label).max(kind);
+        }
+    }
+
+    fn label(&self, address: usize) -> Option<String> {
+        let prefix = match self.labels.get(&address)? {
+            LabelKind::Subroutine => "sub",
+            LabelKind::Jump => "label",
+            LabelKind::Data => "data",
+        };
+        Some(format!("{}_{:03x}", prefix, address))
+    }
+
+    // address operand, by label if there is one
+    fn target(&self, address: u16, syntax: Syntax) -> String {
+        self.label(address as usize)
+            .unwrap_or_else(|| match syntax {
+                Syntax::Octo => format!("0x{:03X}", address),
+                Syntax::Cowgod => format!("{:#05X}", address),
+            })
+    }
+
+    // mnemonic of every instruction followed by its address and raw bytes in a comment,
+    // data in rows of up to 8 bytes, the Octo listing assembles back into the rom
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let mut listing = String::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = PROGRAM_START + offset;
+            if let Some(label) = self.label(address) {
+                match syntax {
+                    Syntax::Octo => writeln!(listing, ": {}", label),
+                    Syntax::Cowgod => writeln!(listing, "{}:", label),
+                }
+                .unwrap();
+            }
+            let length = match self.code[offset] {
+                0 => self.data_row_length(offset),
+                length => length as usize,
+            };
+            let bytes = &self.rom[offset..offset + length];
+            let text = match self.code[offset] {
+                0 => data(bytes, syntax),
+                _ => self.mnemonic(address, syntax),
+            };
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let comment = match syntax {
+                Syntax::Octo => "#",
+                Syntax::Cowgod => ";",
+            };
+            writeln!(
+                listing,
+                "{:<24} {} {:04X}  {}",
+                text,
+                comment,
+                address,
+                hex.join(" ")
+            )
+            .unwrap();
+            offset += length;
+        }
+        listing
+    }
+
+    // data continues up to the next code or label
+    fn data_row_length(&self, offset: usize) -> usize {
+        let mut length = 1;
+        while length < DATA_ROW
+            && offset + length < self.rom.len()
+            && self.code[offset + length] == 0
+            && !self.labels.contains_key(&(PROGRAM_START + offset + length))
+        {
+            length += 1;
+        }
+        length
+    }
+
+    fn mnemonic(&self, address: usize, syntax: Syntax) -> String {
+        let instruction = self.instruction_at(address).unwrap();
+        match syntax {
+            Syntax::Octo => self.octo(address, instruction),
+            Syntax::Cowgod => match instruction {
+                Instruction::Jump { nnn } => format!("JP {}", self.target(nnn, syntax)),
+                Instruction::Call { nnn } => format!("CALL {}", self.target(nnn, syntax)),
+                Instruction::SetIndex { nnn } => format!("LD I, {}", self.target(nnn, syntax)),
+                Instruction::JumpWithOffset { x, nnn } => {
+                    let x = if self.quirks.jump_uses_vx { x } else { 0 };
+                    format!("JP V{:X}, {}", x, self.target(nnn, syntax))
+                }
+                Instruction::LoadLongIndex => {
+                    format!(
+                        "LD I, {:#06X}",
+                        self.word_at(address + 2).unwrap_or_default()
+                    )
+                }
+                _ => instruction.to_string(),
+            },
+        }
+    }
+
+    fn octo(&self, address: usize, instruction: Instruction) -> String {
+        let target = |nnn: u16| self.target(nnn, Syntax::Octo);
+        match instruction {
+            Instruction::ScrollDown { n } => format!("scroll-down {}", n),
+            Instruction::ScrollUp { n } => format!("scroll-up {}", n),
+            Instruction::ClearScreen => "clear".to_string(),
+            Instruction::Return => "return".to_string(),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::LowRes => "lores".to_string(),
+            Instruction::HighRes => "hires".to_string(),
+            Instruction::LoadLongIndex => format!(
+                "i := long 0x{:04X}",
+                self.word_at(address + 2).unwrap_or_default()
+            ),
+            Instruction::SelectPlanes { x } => format!("plane {}", x),
+            Instruction::LoadAudioPattern => "audio".to_string(),
+            Instruction::Jump { nnn } => format!("jump {}", target(nnn)),
+            Instruction::Call { nnn } => format!(":call {}", target(nnn)),
+            Instruction::SkipIfEqImm { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+            Instruction::SkipIfNeqImm { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+            Instruction::SkipIfEq { x, y } => format!("if v{:x} != v{:x} then", x, y),
+            Instruction::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+            Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+            Instruction::SetImm { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+            Instruction::AddImm { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+            Instruction::Set { x, y } => format!("v{:x} := v{:x}", x, y),
+            Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+            Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+            Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+            Instruction::Add { x, y } => format!("v{:x} += v{:x}", x, y),
+            Instruction::SubXY { x, y } => format!("v{:x} -= v{:x}", x, y),
+            Instruction::ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+            Instruction::SubYX { x, y } => format!("v{:x} =- v{:x}", x, y),
+            Instruction::ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+            Instruction::SkipIfNeq { x, y } => format!("if v{:x} == v{:x} then", x, y),
+            Instruction::SetIndex { nnn } => format!("i := {}", target(nnn)),
+            Instruction::JumpWithOffset { nnn, .. } => format!("jump0 {}", target(nnn)),
+            Instruction::Random { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+            Instruction::DrawSprite { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::SkipIfKey { x } => format!("if v{:x} -key then", x),
+            Instruction::SkipIfNotKey { x } => format!("if v{:x} key then", x),
+            Instruction::GetDelay { x } => format!("v{:x} := delay", x),
+            Instruction::WaitForKey { x } => format!("v{:x} := key", x),
+            Instruction::SetDelay { x } => format!("delay := v{:x}", x),
+            Instruction::SetSound { x } => format!("buzzer := v{:x}", x),
+            Instruction::AddToIndex { x } => format!("i += v{:x}", x),
+            Instruction::FontChar { x } => format!("i := hex v{:x}", x),
+            Instruction::BigFontChar { x } => format!("i := bighex v{:x}", x),
+            Instruction::ToDigits { x } => format!("bcd v{:x}", x),
+            Instruction::StoreRegisters { x } => format!("save v{:x}", x),
+            Instruction::LoadRegisters { x } => format!("load v{:x}", x),
+            Instruction::StoreFlags { x } => format!("saveflags v{:x}", x),
+            Instruction::LoadFlags { x } => format!("loadflags v{:x}", x),
+            Instruction::SetPitch { x } => format!("pitch := v{:x}", x),
+        }
+    }
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+    match syntax {
+        Syntax::Octo => bytes.join(" "),
+        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+    }
+}
+
+pub fn disassemble(rom: &[u8], mode: Mode, quirks: Quirks, syntax: Syntax) -> String {
+    Disassembly::new(rom, mode, quirks).listing(syntax)
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(syntax: &str) -> Result<Self, Self::Err> {
+        match syntax {
+            "octo" => Ok(Syntax::Octo),
+            "cowgod" => Ok(Syntax::Cowgod),
+            _ => Err(format!(
+                "unknown syntax: {}, expected octo or cowgod",
+                syntax
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn targets_inside_instructions_are_numbers() {
+        let rom = [
+            0x22, 0x06, // :call sub_206
+            0xA2, 0x09, // i := 0x209, inside i := long
+            0x12, 0x05, // jump 0x205, its own second byte
+            0xF0, 0x00, 0x02, 0x10, // i := long 0x0210
+            0x00, 0xEE, // return
+            0x01, 0x02, 0x03,
+        ];
+        let listing = disassemble(&rom, Mode::XoChip, Quirks::default(), Syntax::Octo);
+        assert!(listing.contains(": sub_206\n"));
+        assert!(listing.contains("i := 0x209 "));
+        assert!(listing.contains("jump 0x205 "));
+        assert_eq!(assemble(&listing).unwrap(), rom);
+    }
+
+    #[test]
+    fn data_rows_split_at_odd_labels() {
+        let rom = [
+            0xA2, 0x05, // i := data_205
+            0x00, 0xFD, // exit
+            0x01, 0x02, 0x03, 0x04,
+        ];
+        let listing = disassemble(&rom, Mode::SuperChip, Quirks::default(), Syntax::Octo);
+        assert!(listing.contains(": data_205\n0x02 0x03 0x04 "));
+        assert_eq!(assemble(&listing).unwrap(), rom);
+    }
+
+    #[test]
+    fn offset_jumps_follow_the_jump_quirk() {
+        let rom = [0xB4, 0x56];
+        let listing = |preset: &str| {
+            let quirks = Quirks::preset(preset).unwrap();
+            disassemble(&rom, Mode::Chip8, quirks, Syntax::Cowgod)
+        };
+        assert!(listing("cosmac-vip").starts_with("JP V0, 0x456 "));
+        assert!(listing("chip48").starts_with("JP V4, 0x456 "));
+    }
+}
@@ -1,19 +1,44 @@
-use bitvec::vec::BitVec;
-use bitvec::{bits, bitvec};
+use crate::session::{Frontend, StateRequest};
+use chip8::config::Hotkeys;
+use chip8::framebuffer::{Framebuffer, LORES_HEIGHT, LORES_WIDTH};
+use chip8::frontend::{Display, Keypad};
+use chip8::keymap::Keymap;
+use chip8::palette::Palette;
 use sfml::graphics::{
     Color, PrimitiveType, RenderStates, RenderTarget, RenderWindow, Vertex, View,
 };
 use sfml::system::Vector2f;
 use sfml::window::{ContextSettings, Event, Key, Style, VideoMode};
 
+// keys selecting a save state slot together with shift
+const SLOT_KEYS: [Key; 10] = [
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+
 /*
 Screen:
-    white_pixels:
+    pixel_vertices:
         VertexArray with size:
-            screen_width * screen_height * verticies_per_rectangle
-    pixel_state:
-        - stores the color of each pixel
-        - either black or white (0 or 1)
+            lit_pixels * verticies_per_rectangle
+    pixel_colors:
+        - copy of the last drawn framebuffer colors, used to redraw on resize
+    resolution:
+        - (width,height) of the last drawn framebuffer in pixels
+    palette:
+        - colors of the background and the XO-CHIP bitplanes
+    keymap:
+        - keyboard keys pressing the CHIP-8 keys
+    hotkeys:
+        - keys saving / loading states, rewinding and recording
     window:
         - sfml RenderWindow
     size:
@@ -22,21 +47,47 @@ Screen:
         - stores key state of every key
     quit_flag:
         - window closed event flag
+    slot:
+        - save state slot used by F5 / F9, selected with shift + 0-9
+    state_request:
+        - save state hotkey pressed since the last take_state_request
+    rewind_held:
+        - the rewind key (backspace) is held down
+    record_toggled:
+        - the record key (F12) was pressed since the last take_record_toggle
 */
 pub struct Screen {
-    white_pixels: Vec<Vertex>,
-    pixel_state: BitVec,
+    pixel_vertices: Vec<Vertex>,
+    pixel_colors: Vec<u8>,
+    resolution: (usize, usize),
+    palette: Palette,
+    keymap: Keymap,
+    hotkeys: Hotkeys,
     window: RenderWindow,
     size: (u32, u32),
     key_flags: u16,
     quit_flag: bool,
+    slot: u8,
+    state_request: Option<StateRequest>,
+    rewind_held: bool,
+    record_toggled: bool,
 }
 
 impl Screen {
-    pub fn new(size: (u32, u32), title: &str) -> Self {
+    pub fn new(
+        size: (u32, u32),
+        title: &str,
+        palette: Palette,
+        keymap: Keymap,
+        hotkeys: Hotkeys,
+    ) -> Self {
         let mut screen = Screen {
-            white_pixels: vec![],
-            pixel_state: bitvec![0;64*32],
+            pixel_vertices: vec![],
+            pixel_colors: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            resolution: (LORES_WIDTH, LORES_HEIGHT),
+            palette,
+            keymap,
+            hotkeys,
             window: RenderWindow::new(
                 VideoMode::new(size.0, size.1, 32),
                 title,
@@ -45,6 +96,10 @@ impl Screen {
             ),
             key_flags: 0,
             quit_flag: false,
+            slot: 0,
+            state_request: None,
+            rewind_held: false,
+            record_toggled: false,
             size,
         };
 
@@ -57,75 +112,114 @@ impl Screen {
         screen
     }
 
-    pub fn _debug_str(&self) -> String {
-        let mut debug_str = String::with_capacity(32 * 64);
-        for row in 0..32u8 {
-            for col in 0..64u8 {
-                debug_str.push(if self.get_pixel(col, row).unwrap() {
-                    '*'
-                } else {
-                    ' '
-                });
-            }
-            debug_str.push('\n');
-        }
-        debug_str
-    }
-
     pub fn update_screen(&mut self) {
-        let pixel_width = self.size.0 as f32 / 64f32;
-        let pixel_height = self.size.1 as f32 / 32f32;
-        let ones = self.pixel_state.count_ones();
-        let pixel_length = self.white_pixels.len();
+        let (width, height) = self.resolution;
+        let pixel_width = self.size.0 as f32 / width as f32;
+        let pixel_height = self.size.1 as f32 / height as f32;
+        let palette = self.palette;
 
-        if ones > pixel_length {
-            self.white_pixels.reserve(ones - pixel_length);
-        }
-        self.white_pixels.clear();
-        self.white_pixels.extend(
-            self.pixel_state
-                .iter_ones()
-                .map(|pixel| {
-                    let (row, col) = (pixel / 64 as usize, pixel % 64 as usize);
+        self.pixel_vertices.clear();
+        self.pixel_vertices.extend(
+            self.pixel_colors
+                .iter()
+                .enumerate()
+                .filter(|(_, color)| **color != 0)
+                .flat_map(|(pixel, color)| {
+                    let [r, g, b] = palette.color(*color);
+                    let color = Color::rgb(r, g, b);
+                    let (row, col) = (pixel / width, pixel % width);
                     let (x_off, y_off) = (col as f32 * pixel_width, row as f32 * pixel_height);
                     [
-                        Vertex::new(
-                            Vector2f::new(x_off, y_off),
-                            Color::WHITE,
-                            Vector2f::default(),
-                        ),
+                        Vertex::new(Vector2f::new(x_off, y_off), color, Vector2f::default()),
                         Vertex::new(
                             Vector2f::new(x_off + pixel_width, y_off),
-                            Color::WHITE,
+                            color,
                             Vector2f::default(),
                         ),
                         Vertex::new(
                             Vector2f::new(x_off + pixel_width, y_off + pixel_height),
-                            Color::WHITE,
+                            color,
                             Vector2f::default(),
                         ),
                         Vertex::new(
                             Vector2f::new(x_off, y_off + pixel_height),
-                            Color::WHITE,
+                            color,
                             Vector2f::default(),
                         ),
                     ]
-                })
-                .flatten(),
+                }),
         );
     }
 
-    pub fn handle_events(&mut self) {
-        for event in self.window.poll_event() {
+    fn key_released(&mut self, key: Key) {
+        let name = Some(format!("{:?}", key));
+        if self.hotkeys.rewind == name {
+            self.rewind_held = false;
+        }
+        if let Some(chip8_key) = name.and_then(|name| self.keymap.key(&name)) {
+            self.key_flags &= !(1u16 << chip8_key);
+        }
+    }
+
+    fn key_pressed(&mut self, key: Key, shift: bool) {
+        if let Some(slot) = SLOT_KEYS.iter().position(|slot_key| *slot_key == key) {
+            if shift {
+                self.slot = slot as u8;
+                println!("save state slot {}", slot);
+                return;
+            }
+        }
+        let name = Some(format!("{:?}", key));
+        if self.hotkeys.save_state == name {
+            self.state_request = Some(StateRequest::Save(self.slot));
+        }
+        if self.hotkeys.load_state == name {
+            self.state_request = Some(StateRequest::Load(self.slot));
+        }
+        if self.hotkeys.rewind == name {
+            self.rewind_held = true;
+        }
+        if self.hotkeys.record == name {
+            self.record_toggled = true;
+        }
+        if let Some(chip8_key) = name.and_then(|name| self.keymap.key(&name)) {
+            self.key_flags |= 1u16 << chip8_key;
+        }
+    }
+
+    fn redraw(&mut self) {
+        let [r, g, b] = self.palette.color(0);
+        self.window.clear(Color::rgb(r, g, b));
+        self.update_screen();
+        self.window.draw_primitives(
+            &self.pixel_vertices,
+            PrimitiveType::QUADS,
+            &RenderStates::default(),
+        );
+        self.window.display();
+    }
+}
+
+impl Display for Screen {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.pixel_colors = framebuffer.colors();
+        self.resolution = (framebuffer.width(), framebuffer.height());
+        self.redraw()
+    }
+}
+
+impl Keypad for Screen {
+    fn handle_events(&mut self) {
+        while let Some(event) = self.window.poll_event() {
             match event {
                 Event::Closed => self.quit_flag = true,
                 Event::KeyPressed {
                     code: key,
                     alt: _,
                     ctrl: _,
-                    shift: _,
+                    shift,
                     system: _,
-                } => self.key_pressed(key),
+                } => self.key_pressed(key, shift),
                 Event::KeyReleased {
                     code: key,
                     alt: _,
@@ -140,112 +234,32 @@ impl Screen {
                         Vector2f::new(width as f32, height as f32),
                     );
                     self.window.set_view(&visible_area);
-                    self.draw()
+                    self.redraw()
                 }
                 _ => (),
             }
         }
     }
 
-    fn key_released(&mut self, key: Key) {
-        match key {
-            Key::X => self.key_flags &= !1u16,
-            Key::Num1 => self.key_flags &= !(1u16 << 1),
-            Key::Num2 => self.key_flags &= !(1u16 << 2),
-            Key::Num3 => self.key_flags &= !(1u16 << 3),
-            Key::Q => self.key_flags &= !(1u16 << 4),
-            Key::W => self.key_flags &= !(1u16 << 5),
-            Key::E => self.key_flags &= !(1u16 << 6),
-            Key::A => self.key_flags &= !(1u16 << 7),
-            Key::S => self.key_flags &= !(1u16 << 8),
-            Key::D => self.key_flags &= !(1u16 << 9),
-            Key::Y => self.key_flags &= !(1u16 << 10),
-            Key::C => self.key_flags &= !(1u16 << 11),
-            Key::Num4 => self.key_flags &= !(1u16 << 12),
-            Key::R => self.key_flags &= !(1u16 << 13),
-            Key::F => self.key_flags &= !(1u16 << 14),
-            Key::V => self.key_flags &= !(1u16 << 15),
-            _ => (),
-        }
-    }
-
-    fn key_pressed(&mut self, key: Key) {
-        match key {
-            Key::X => self.key_flags |= 1u16,
-            Key::Num1 => self.key_flags |= 1u16 << 1,
-            Key::Num2 => self.key_flags |= 1u16 << 2,
-            Key::Num3 => self.key_flags |= 1u16 << 3,
-            Key::Q => self.key_flags |= 1u16 << 4,
-            Key::W => self.key_flags |= 1u16 << 5,
-            Key::E => self.key_flags |= 1u16 << 6,
-            Key::A => self.key_flags |= 1u16 << 7,
-            Key::S => self.key_flags |= 1u16 << 8,
-            Key::D => self.key_flags |= 1u16 << 9,
-            Key::Y => self.key_flags |= 1u16 << 10,
-            Key::C => self.key_flags |= 1u16 << 11,
-            Key::Num4 => self.key_flags |= 1u16 << 12,
-            Key::R => self.key_flags |= 1u16 << 13,
-            Key::F => self.key_flags |= 1u16 << 14,
-            Key::V => self.key_flags |= 1u16 << 15,
-            _ => (),
-        }
-    }
-
-    pub fn any_key_pressed(&self) -> bool {
-        self.key_flags > 0
-    }
-
-    pub fn get_pressed_key(&self) -> u8 {
-        for key in 0..16u8 {
-            if self.key_flags & (1u16 << key) > 0 {
-                return key;
-            }
-        }
-        panic!("Function: get_pressed_key was called without checking if a key was pressed!")
+    fn key_flags(&self) -> u16 {
+        self.key_flags
     }
 
-    pub fn key_state(&self, key: u8) -> Result<bool, String> {
-        if key > 0xF {
-            return Err(format!("Invalid key, key must be 0x0-0xF, key: {}", key));
-        }
-        Ok(self.key_flags & (1u16 << key) > 0)
-    }
-
-    pub fn closed(&self) -> bool {
+    fn closed(&self) -> bool {
         self.quit_flag
     }
+}
 
-    pub fn draw(&mut self) {
-        self.window.clear(Color::BLACK);
-        self.update_screen();
-        self.window.draw_primitives(
-            &self.white_pixels,
-            PrimitiveType::QUADS,
-            &RenderStates::default(),
-        );
-        self.window.display();
-    }
-
-    pub fn clear(&mut self) {
-        self.pixel_state &= bits![0; 64*32];
-        self.draw()
+impl Frontend for Screen {
+    fn take_state_request(&mut self) -> Option<StateRequest> {
+        self.state_request.take()
     }
 
-    pub fn set_pixel(&mut self, x: u8, y: u8, pixel: bool) {
-        self.pixel_state.set(pos_to_index(x, y), pixel);
+    fn take_record_toggle(&mut self) -> bool {
+        std::mem::take(&mut self.record_toggled)
     }
 
-    pub fn get_pixel(&self, x: u8, y: u8) -> Result<bool, String> {
-        match self.pixel_state.get(pos_to_index(x, y)) {
-            Some(pixel_state) => Ok(*pixel_state.as_ref()),
-            None => Err(format!(
-                "Accessed invalid pixel postion: x: {}, y: {}",
-                x, y
-            )),
-        }
+    fn rewinding(&self) -> bool {
+        self.rewind_held
     }
 }
-
-fn pos_to_index(x: u8, y: u8) -> usize {
-    (x as usize) + (y as usize) * 64
-}
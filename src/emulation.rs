@@ -0,0 +1,200 @@
+use chip8::audio::AudioRecorder;
+use chip8::debugger::Debugger;
+use chip8::image::Screenshots;
+use chip8::movie::Movie;
+use chip8::rewind::Rewind;
+use chip8::timing::Scheduler;
+use chip8::trace::Tracer;
+use chip8::video::VideoRecorder;
+use chip8::{Chip8, Chip8Error};
+use std::fs::File;
+use std::io::BufWriter;
+
+/*
+Emulation:
+    - a loaded program and everything recorded while it runs
+    chip8:
+        - the interpreter
+    scheduler:
+        - how many instructions run per frame
+    frame:
+        - number of emulated frames
+    frame_limit:
+        - stop after this many frames (--frames)
+    recorder:
+        - sound of every frame for --wav
+    tracer:
+        - log of every executed instruction for --trace
+    rewind:
+        - states of the last frames, stepped back through while the rewind key is held
+    screenshots:
+        - PNG images of every n-th frame for --screenshot-every
+    video:
+        - GIF or Y4M recording of every frame for --record or the record hotkey
+    movie:
+        - keypad input of every frame for --record-movie
+    replay:
+        - movie whose input replaces the keypad for --replay, until it runs out
+    debugger:
+        - breakpoints and stepping for --debug, a paused debugger stops the frame
+*/
+pub struct Emulation {
+    pub chip8: Chip8,
+    scheduler: Scheduler,
+    frame: u64,
+    frame_limit: Option<u64>,
+    recorder: Option<AudioRecorder>,
+    tracer: Option<Tracer<BufWriter<File>>>,
+    rewind: Option<Rewind>,
+    screenshots: Option<Screenshots>,
+    video: Option<VideoRecorder>,
+    movie: Option<Movie>,
+    replay: Option<Movie>,
+    pub debugger: Option<Debugger>,
+}
+
+impl Emulation {
+    pub fn new(chip8: Chip8, scheduler: Scheduler, frame_limit: Option<u64>) -> Self {
+        Emulation {
+            chip8,
+            scheduler,
+            frame: 0,
+            frame_limit,
+            recorder: None,
+            tracer: None,
+            rewind: None,
+            screenshots: None,
+            video: None,
+            movie: None,
+            replay: None,
+            debugger: None,
+        }
+    }
+
+    pub fn set_recorder(&mut self, recorder: AudioRecorder) {
+        self.recorder = Some(recorder)
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer<BufWriter<File>>) {
+        self.tracer = Some(tracer)
+    }
+
+    pub fn set_rewind(&mut self, mut rewind: Rewind) {
+        rewind.record(&self.chip8);
+        self.rewind = Some(rewind)
+    }
+
+    pub fn set_screenshots(&mut self, screenshots: Screenshots) {
+        self.screenshots = Some(screenshots)
+    }
+
+    pub fn set_video(&mut self, video: VideoRecorder) {
+        self.video = Some(video)
+    }
+
+    // stops the recording, it still has to be finished
+    pub fn take_video(&mut self) -> Option<VideoRecorder> {
+        self.video.take()
+    }
+
+    pub fn set_movie(&mut self, movie: Movie) {
+        self.movie = Some(movie)
+    }
+
+    pub fn take_movie(&mut self) -> Option<Movie> {
+        self.movie.take()
+    }
+
+    pub fn set_replay(&mut self, replay: Movie) {
+        self.replay = Some(replay)
+    }
+
+    // loading a state would make the run differ from the movie
+    #[cfg(any(feature = "sfml", feature = "tui"))]
+    pub fn playing_movie(&self) -> bool {
+        self.movie.is_some() || self.replay.is_some()
+    }
+
+    pub fn recorder(&self) -> Option<&AudioRecorder> {
+        self.recorder.as_ref()
+    }
+
+    // run the rest of the current frame, unless the debugger stops it first
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        if let Some(replay) = self
+            .replay
+            .as_ref()
+            .filter(|replay| self.frame < replay.frames)
+        {
+            self.chip8.set_key_flags(replay.key_flags(self.frame));
+        }
+        if let Some(movie) = &mut self.movie {
+            movie.record(self.frame, self.chip8.key_flags());
+        }
+        loop {
+            if let Some(debugger) = &mut self.debugger {
+                if debugger.should_break(&mut self.chip8) {
+                    return Ok(());
+                }
+            }
+            let frame_done = match &mut self.tracer {
+                Some(tracer) => tracer.step_in_frame(&mut self.chip8, self.scheduler)?,
+                None => self.chip8.step_in_frame(self.scheduler)?,
+            };
+            if frame_done {
+                break;
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_frame(&self.chip8);
+        }
+        if let Some(rewind) = &mut self.rewind {
+            rewind.record(&self.chip8);
+        }
+        self.frame += 1;
+        if let Some(screenshots) = &mut self.screenshots {
+            screenshots.capture(self.frame, self.chip8.framebuffer())?;
+        }
+        if let Some(video) = &mut self.video {
+            video.capture(self.chip8.framebuffer())?;
+        }
+        Ok(())
+    }
+
+    // go back to the previous frame, false once there is nothing left to rewind
+    #[cfg(any(feature = "sfml", feature = "tui"))]
+    pub fn rewind_frame(&mut self) -> Result<bool, Chip8Error> {
+        let Some(rewind) = &mut self.rewind else {
+            return Ok(false);
+        };
+        let rewound = rewind.rewind(&mut self.chip8)?;
+        if rewound {
+            self.frame = self.frame.saturating_sub(1);
+        }
+        Ok(rewound)
+    }
+
+    // with a debugger, stop at the faulting instruction instead of failing
+    #[cfg(any(feature = "sfml", feature = "tui"))]
+    pub fn catch_fault(&mut self, err: Chip8Error) -> Result<(), Chip8Error> {
+        let (Some(debugger), Some(pc)) = (&mut self.debugger, err.pc()) else {
+            return Err(err);
+        };
+        println!("{}", err);
+        self.chip8.memory_mut().jump_pc(pc);
+        debugger.fault();
+        Ok(())
+    }
+
+    pub fn flush_trace(&mut self) -> Result<(), Chip8Error> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush()?;
+        }
+        Ok(())
+    }
+
+    // the program exited or the frame limit was reached
+    pub fn finished(&self) -> bool {
+        self.chip8.exited() || self.frame_limit.is_some_and(|limit| self.frame >= limit)
+    }
+}
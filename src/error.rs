@@ -0,0 +1,217 @@
+use std::{fmt, io};
+
+/*
+Fault:
+    - what went wrong inside a single instruction
+    - raised by Memory / Framebuffer, which don't know the faulting instruction
+    - turned into a Chip8Error by the machine, which adds pc and op code
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    StackUnderflow,
+    StackOverflow,
+    UnknownOpcode,
+    OutOfBounds { address: usize },
+    InvalidRegister { register: u8 },
+    InvalidKey { key: u8 },
+    InvalidPixel { x: u8, y: u8 },
+}
+
+/*
+Chip8Error:
+    - pc: address of the faulting instruction
+    - op_code: raw faulting instruction (0x0000 if it couldn't be fetched)
+    - line: source line of an assembly error
+*/
+#[derive(Debug)]
+pub enum Chip8Error {
+    StackUnderflow {
+        pc: u16,
+        op_code: u16,
+    },
+    StackOverflow {
+        pc: u16,
+        op_code: u16,
+    },
+    UnknownOpcode {
+        pc: u16,
+        op_code: u16,
+    },
+    OutOfBounds {
+        pc: u16,
+        op_code: u16,
+        address: usize,
+    },
+    InvalidRegister {
+        pc: u16,
+        op_code: u16,
+        register: u8,
+    },
+    InvalidKey {
+        pc: u16,
+        op_code: u16,
+        key: u8,
+    },
+    InvalidPixel {
+        pc: u16,
+        op_code: u16,
+        x: u8,
+        y: u8,
+    },
+    RomTooLarge {
+        size: usize,
+        capacity: usize,
+    },
+    Assembly {
+        line: usize,
+        message: String,
+    },
+    InvalidState {
+        message: String,
+    },
+    InvalidMovie {
+        message: String,
+    },
+    Config {
+        message: String,
+    },
+    Io(io::Error),
+}
+
+impl Chip8Error {
+    pub fn from_fault(fault: Fault, pc: u16, op_code: u16) -> Self {
+        match fault {
+            Fault::StackUnderflow => Chip8Error::StackUnderflow { pc, op_code },
+            Fault::StackOverflow => Chip8Error::StackOverflow { pc, op_code },
+            Fault::UnknownOpcode => Chip8Error::UnknownOpcode { pc, op_code },
+            Fault::OutOfBounds { address } => Chip8Error::OutOfBounds {
+                pc,
+                op_code,
+                address,
+            },
+            Fault::InvalidRegister { register } => Chip8Error::InvalidRegister {
+                pc,
+                op_code,
+                register,
+            },
+            Fault::InvalidKey { key } => Chip8Error::InvalidKey { pc, op_code, key },
+            Fault::InvalidPixel { x, y } => Chip8Error::InvalidPixel { pc, op_code, x, y },
+        }
+    }
+
+    pub fn pc(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::StackUnderflow { pc, .. }
+            | Chip8Error::StackOverflow { pc, .. }
+            | Chip8Error::UnknownOpcode { pc, .. }
+            | Chip8Error::OutOfBounds { pc, .. }
+            | Chip8Error::InvalidRegister { pc, .. }
+            | Chip8Error::InvalidKey { pc, .. }
+            | Chip8Error::InvalidPixel { pc, .. } => Some(pc),
+            Chip8Error::RomTooLarge { .. }
+            | Chip8Error::Assembly { .. }
+            | Chip8Error::InvalidState { .. }
+            | Chip8Error::InvalidMovie { .. }
+            | Chip8Error::Config { .. }
+            | Chip8Error::Io(_) => None,
+        }
+    }
+
+    pub fn op_code(&self) -> Option<u16> {
+        match *self {
+            Chip8Error::StackUnderflow { op_code, .. }
+            | Chip8Error::StackOverflow { op_code, .. }
+            | Chip8Error::UnknownOpcode { op_code, .. }
+            | Chip8Error::OutOfBounds { op_code, .. }
+            | Chip8Error::InvalidRegister { op_code, .. }
+            | Chip8Error::InvalidKey { op_code, .. }
+            | Chip8Error::InvalidPixel { op_code, .. } => Some(op_code),
+            Chip8Error::RomTooLarge { .. }
+            | Chip8Error::Assembly { .. }
+            | Chip8Error::InvalidState { .. }
+            | Chip8Error::InvalidMovie { .. }
+            | Chip8Error::Config { .. }
+            | Chip8Error::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Fault::StackUnderflow => write!(f, "pop called on empty stack"),
+            Fault::StackOverflow => write!(f, "push called on full stack"),
+            Fault::UnknownOpcode => write!(f, "invalid op code"),
+            Fault::OutOfBounds { address } => {
+                write!(f, "ram access out of bounds, address: {:#06x}", address)
+            }
+            Fault::InvalidRegister { register } => write!(
+                f,
+                "var register id is out of range, must be 0x0-0xF id: {}",
+                register
+            ),
+            Fault::InvalidKey { key } => {
+                write!(f, "invalid key, key must be 0x0-0xF, key: {}", key)
+            }
+            Fault::InvalidPixel { x, y } => {
+                write!(f, "accessed invalid pixel position: x: {}, y: {}", x, y)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fault = match *self {
+            Chip8Error::RomTooLarge { size, capacity } => {
+                return write!(
+                    f,
+                    "rom is too large: {} bytes, at most {} bytes fit into ram",
+                    size, capacity
+                )
+            }
+            Chip8Error::Assembly { line, ref message } => {
+                return write!(f, "assembly error on line {}: {}", line, message)
+            }
+            Chip8Error::InvalidState { ref message } => {
+                return write!(f, "invalid save state: {}", message)
+            }
+            Chip8Error::InvalidMovie { ref message } => {
+                return write!(f, "invalid movie: {}", message)
+            }
+            Chip8Error::Config { ref message } => return write!(f, "config error: {}", message),
+            Chip8Error::Io(ref err) => return write!(f, "i/o error: {}", err),
+            Chip8Error::StackUnderflow { .. } => Fault::StackUnderflow,
+            Chip8Error::StackOverflow { .. } => Fault::StackOverflow,
+            Chip8Error::UnknownOpcode { .. } => Fault::UnknownOpcode,
+            Chip8Error::OutOfBounds { address, .. } => Fault::OutOfBounds { address },
+            Chip8Error::InvalidRegister { register, .. } => Fault::InvalidRegister { register },
+            Chip8Error::InvalidKey { key, .. } => Fault::InvalidKey { key },
+            Chip8Error::InvalidPixel { x, y, .. } => Fault::InvalidPixel { x, y },
+        };
+        write!(
+            f,
+            "error in instruction {:#06x} at {:#06x}: {}",
+            self.op_code().unwrap_or_default(),
+            self.pc().unwrap_or_default(),
+            fault
+        )
+    }
+}
+
+impl std::error::Error for Fault {}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(err: io::Error) -> Self {
+        Chip8Error::Io(err)
+    }
+}
@@ -0,0 +1,242 @@
+use crate::error::{Chip8Error, Fault};
+use crate::state::{invalid, StateReader, StateWriter};
+use bitvec::bitvec;
+use bitvec::vec::BitVec;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const PLANES: usize = 2;
+
+/*
+Framebuffer:
+    planes:
+        - one bit per pixel for each of the two XO-CHIP bitplanes
+        - the color of a pixel is plane 0 | plane 1 << 1 (0-3)
+        - CHIP-8 and SUPER-CHIP only ever draw to plane 0
+    selected_planes:
+        - bitmask of the planes drawn, cleared and scrolled (XO-CHIP FN01)
+    hires:
+        - 128x64 SUPER-CHIP resolution instead of 64x32
+    dirty:
+        - set when the pixel state changed since the last redraw
+*/
+pub struct Framebuffer {
+    planes: [BitVec; PLANES],
+    selected_planes: u8,
+    hires: bool,
+    dirty: bool,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            planes: [
+                bitvec![0; LORES_WIDTH * LORES_HEIGHT],
+                bitvec![0; LORES_WIDTH * LORES_HEIGHT],
+            ],
+            selected_planes: 1,
+            hires: false,
+            dirty: true,
+        }
+    }
+
+    pub fn debug_str(&self) -> String {
+        let mut debug_str = String::with_capacity((self.width() + 1) * self.height());
+        for row in 0..self.height() {
+            for col in 0..self.width() {
+                debug_str.push([' ', '*', '+', '#'][self.color(col, row) as usize]);
+            }
+            debug_str.push('\n');
+        }
+        debug_str
+    }
+
+    pub fn plane(&self, plane: usize) -> &BitVec {
+        &self.planes[plane]
+    }
+
+    // color (0-3) of every pixel, row by row
+    pub fn colors(&self) -> Vec<u8> {
+        self.planes[0]
+            .iter()
+            .zip(self.planes[1].iter())
+            .map(|(low, high)| *low as u8 | (*high as u8) << 1)
+            .collect()
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        let index = self.pos_to_index(x, y);
+        self.planes[0][index] as u8 | (self.planes[1][index] as u8) << 1
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    // switching the resolution clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        for plane in self.planes.iter_mut() {
+            *plane = bitvec![0; LORES_WIDTH * LORES_HEIGHT * if hires { 4 } else { 1 }];
+        }
+        self.dirty = true;
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & 0b11
+    }
+
+    // indices of the selected planes in drawing order
+    pub fn selected(&self) -> impl Iterator<Item = usize> {
+        let selected_planes = self.selected_planes;
+        (0..PLANES).filter(move |plane| selected_planes & (1 << plane) != 0)
+    }
+
+    pub fn clear(&mut self) {
+        for plane in self.selected().collect::<Vec<_>>() {
+            self.planes[plane].fill(false);
+        }
+        self.dirty = true;
+    }
+
+    pub fn set_pixel(&mut self, plane: usize, x: u8, y: u8, pixel: bool) {
+        let index = self.pos_to_index(x as usize, y as usize);
+        self.planes[plane].set(index, pixel);
+        self.dirty = true;
+    }
+
+    pub fn get_pixel(&self, plane: usize, x: u8, y: u8) -> Result<bool, Fault> {
+        if x as usize >= self.width() || y as usize >= self.height() {
+            return Err(Fault::InvalidPixel { x, y });
+        }
+        Ok(self.planes[plane][self.pos_to_index(x as usize, y as usize)])
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize))
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize)
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0)
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0)
+    }
+
+    // move every pixel of the selected planes by (dx, dy), pixels moved out of the screen are lost
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        for plane in self.selected().collect::<Vec<_>>() {
+            let mut scrolled = bitvec![0; self.planes[plane].len()];
+            for pixel in self.planes[plane].iter_ones() {
+                let x = (pixel as isize % width) + dx;
+                let y = (pixel as isize / width) + dy;
+                if (0..width).contains(&x) && (0..height).contains(&y) {
+                    scrolled.set(self.pos_to_index(x as usize, y as usize), true);
+                }
+            }
+            self.planes[plane] = scrolled;
+        }
+        self.dirty = true;
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    // planes are packed 8 pixels per byte, first pixel in the lowest bit
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.hires);
+        writer.u8(self.selected_planes);
+        for plane in &self.planes {
+            let packed: Vec<u8> = plane
+                .chunks(8)
+                .map(|pixels| {
+                    pixels
+                        .iter()
+                        .enumerate()
+                        .fold(0, |byte, (bit, pixel)| byte | (*pixel as u8) << bit)
+                })
+                .collect();
+            writer.array(&packed);
+        }
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Result<Self, Chip8Error> {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_hires(reader.bool()?);
+        let selected_planes = reader.u8()?;
+        if selected_planes > 0b11 {
+            return Err(invalid(format!("unknown planes {:#b}", selected_planes)));
+        }
+        framebuffer.select_planes(selected_planes);
+        for plane in framebuffer.planes.iter_mut() {
+            let pixels = plane.len();
+            for (index, byte) in reader.take(pixels / 8)?.iter().enumerate() {
+                for bit in 0..8 {
+                    plane.set(index * 8 + bit, byte & (1 << bit) != 0);
+                }
+            }
+        }
+        Ok(framebuffer)
+    }
+
+    fn pos_to_index(&self, x: usize, y: usize) -> usize {
+        x + y * self.width()
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_combine_both_planes() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_pixel(0, 1, 0, true);
+        framebuffer.set_pixel(1, 2, 0, true);
+        framebuffer.set_pixel(0, 3, 0, true);
+        framebuffer.set_pixel(1, 3, 0, true);
+        let colors = framebuffer.colors();
+        assert_eq!(colors.len(), LORES_WIDTH * LORES_HEIGHT);
+        assert_eq!(colors[..5], [0, 1, 2, 3, 0]);
+        assert_eq!(&framebuffer.debug_str()[..5], " *+# ");
+    }
+}
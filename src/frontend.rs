@@ -0,0 +1,109 @@
+use crate::audio::Sound;
+use crate::framebuffer::Framebuffer;
+
+// Something the framebuffer can be presented on (window, terminal, image, ...)
+pub trait Display {
+    fn present(&mut self, framebuffer: &Framebuffer);
+}
+
+// Source of the 16 key states (bit n => key n) and of the quit request
+pub trait Keypad {
+    fn handle_events(&mut self);
+    fn key_flags(&self) -> u16;
+    fn closed(&self) -> bool;
+}
+
+// Something that beeps while the sound timer is running (speaker, wav file, ...)
+pub trait Speaker {
+    // called every frame with what the beeper should play
+    fn play(&mut self, sound: Sound);
+}
+
+/*
+MemoryDisplay:
+    pixel_colors:
+        - color (0-3) of every pixel of the last presented framebuffer
+    resolution:
+        - (width,height) of the last presented framebuffer
+    frames:
+        - number of presented frames
+*/
+#[derive(Default)]
+pub struct MemoryDisplay {
+    pixel_colors: Vec<u8>,
+    resolution: (usize, usize),
+    frames: usize,
+}
+
+impl MemoryDisplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pixel_colors(&self) -> &[u8] {
+        &self.pixel_colors
+    }
+
+    pub fn resolution(&self) -> (usize, usize) {
+        self.resolution
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+impl Display for MemoryDisplay {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.pixel_colors = framebuffer.colors();
+        self.resolution = (framebuffer.width(), framebuffer.height());
+        self.frames += 1;
+    }
+}
+
+/*
+ManualKeypad:
+    key_flags:
+        - key state set by the caller
+    quit_flag:
+        - set by the caller to stop the emulation
+*/
+#[derive(Default)]
+pub struct ManualKeypad {
+    key_flags: u16,
+    quit_flag: bool,
+}
+
+impl ManualKeypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if pressed {
+            self.key_flags |= 1u16 << (key & 0xF);
+        } else {
+            self.key_flags &= !(1u16 << (key & 0xF));
+        }
+    }
+
+    pub fn set_key_flags(&mut self, key_flags: u16) {
+        self.key_flags = key_flags
+    }
+
+    pub fn quit(&mut self) {
+        self.quit_flag = true
+    }
+}
+
+impl Keypad for ManualKeypad {
+    fn handle_events(&mut self) {}
+
+    fn key_flags(&self) -> u16 {
+        self.key_flags
+    }
+
+    fn closed(&self) -> bool {
+        self.quit_flag
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::Chip8Error;
+use crate::framebuffer::Framebuffer;
+use crate::palette::Palette;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/*
+Image:
+    - rgb picture of a framebuffer, every CHIP-8 pixel drawn as a scale x scale square
+    width, height:
+        - size in image pixels
+    pixels:
+        - r, g, b bytes of every image pixel, row by row
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_framebuffer(framebuffer: &Framebuffer, palette: &Palette, scale: u32) -> Self {
+        let colors = scaled_colors(framebuffer, scale);
+        Image {
+            width: framebuffer.width() as u32 * scale,
+            height: framebuffer.height() as u32 * scale,
+            pixels: colors
+                .into_iter()
+                .flat_map(|color| palette.color(color))
+                .collect(),
+        }
+    }
+
+    pub fn write_png(&self, writer: impl Write) -> Result<(), Chip8Error> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::from)?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(io::Error::from)?;
+        writer.finish().map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+// color (0-3) of every image pixel, row by row, with every CHIP-8 pixel drawn as a scale x scale square
+pub fn scaled_colors(framebuffer: &Framebuffer, scale: u32) -> Vec<u8> {
+    let scale = scale as usize;
+    let (width, height) = (framebuffer.width() * scale, framebuffer.height() * scale);
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| framebuffer.color(x / scale, y / scale)))
+        .collect()
+}
+
+/*
+Screenshots:
+    - saves every `every`-th frame as dir/frame_<frame number>.png
+    dir:
+        - directory the images are written to, created if missing
+    palette, scale:
+        - look of the images
+*/
+pub struct Screenshots {
+    dir: PathBuf,
+    every: u64,
+    palette: Palette,
+    scale: u32,
+}
+
+impl Screenshots {
+    pub fn new(dir: &Path, every: u64, palette: Palette, scale: u32) -> Result<Self, Chip8Error> {
+        fs::create_dir_all(dir)?;
+        Ok(Screenshots {
+            dir: dir.to_path_buf(),
+            every,
+            palette,
+            scale,
+        })
+    }
+
+    // called after every frame with the number of frames run so far
+    pub fn capture(&mut self, frame: u64, framebuffer: &Framebuffer) -> Result<(), Chip8Error> {
+        if self.every == 0 || !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+        let path = self.dir.join(format!("frame_{:06}.png", frame));
+        let image = Image::from_framebuffer(framebuffer, &self.palette, self.scale);
+        let mut file = BufWriter::new(File::create(path)?);
+        image.write_png(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
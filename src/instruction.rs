@@ -0,0 +1,398 @@
+use crate::mode::Mode;
+use crate::quirks::Quirks;
+use std::fmt;
+
+/*
+Instruction:
+    - decoded form of a single 16 bit op code
+    - x, y: variable register ids (0x0-0xF)
+    - n: 4 bit immediate, nn: 8 bit immediate, nnn: 12 bit address
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    ScrollDown { n: u8 },               // 00CN (SCHIP)
+    ScrollUp { n: u8 },                 // 00DN (XO-CHIP)
+    ClearScreen,                        // 00E0
+    Return,                             // 00EE
+    ScrollRight,                        // 00FB (SCHIP)
+    ScrollLeft,                         // 00FC (SCHIP)
+    Exit,                               // 00FD (SCHIP)
+    LowRes,                             // 00FE (SCHIP)
+    HighRes,                            // 00FF (SCHIP)
+    LoadLongIndex,                      // F000 NNNN (XO-CHIP), NNNN is the next word
+    SelectPlanes { x: u8 },             // FN01 (XO-CHIP), x is the plane mask
+    LoadAudioPattern,                   // F002 (XO-CHIP)
+    Jump { nnn: u16 },                  // 1NNN
+    Call { nnn: u16 },                  // 2NNN
+    SkipIfEqImm { x: u8, nn: u8 },      // 3XNN
+    SkipIfNeqImm { x: u8, nn: u8 },     // 4XNN
+    SkipIfEq { x: u8, y: u8 },          // 5XY0
+    SaveRange { x: u8, y: u8 },         // 5XY2 (XO-CHIP)
+    LoadRange { x: u8, y: u8 },         // 5XY3 (XO-CHIP)
+    SetImm { x: u8, nn: u8 },           // 6XNN
+    AddImm { x: u8, nn: u8 },           // 7XNN
+    Set { x: u8, y: u8 },               // 8XY0
+    Or { x: u8, y: u8 },                // 8XY1
+    And { x: u8, y: u8 },               // 8XY2
+    Xor { x: u8, y: u8 },               // 8XY3
+    Add { x: u8, y: u8 },               // 8XY4
+    SubXY { x: u8, y: u8 },             // 8XY5
+    ShiftRight { x: u8, y: u8 },        // 8XY6
+    SubYX { x: u8, y: u8 },             // 8XY7
+    ShiftLeft { x: u8, y: u8 },         // 8XYE
+    SkipIfNeq { x: u8, y: u8 },         // 9XY0
+    SetIndex { nnn: u16 },              // ANNN
+    JumpWithOffset { x: u8, nnn: u16 }, // BNNN / BXNN
+    Random { x: u8, nn: u8 },           // CXNN
+    DrawSprite { x: u8, y: u8, n: u8 }, // DXYN, DXY0: 16x16 sprite (SCHIP)
+    SkipIfKey { x: u8 },                // EX9E
+    SkipIfNotKey { x: u8 },             // EXA1
+    GetDelay { x: u8 },                 // FX07
+    WaitForKey { x: u8 },               // FX0A
+    SetDelay { x: u8 },                 // FX15
+    SetSound { x: u8 },                 // FX18
+    AddToIndex { x: u8 },               // FX1E
+    FontChar { x: u8 },                 // FX29
+    BigFontChar { x: u8 },              // FX30 (SCHIP)
+    ToDigits { x: u8 },                 // FX33
+    StoreRegisters { x: u8 },           // FX55
+    LoadRegisters { x: u8 },            // FX65
+    StoreFlags { x: u8 },               // FX75 (SCHIP)
+    LoadFlags { x: u8 },                // FX85 (SCHIP)
+    SetPitch { x: u8 },                 // FX3A (XO-CHIP)
+}
+
+impl Instruction {
+    pub fn supported_by(&self, mode: Mode) -> bool {
+        match self {
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::BigFontChar { .. }
+            | Instruction::StoreFlags { .. }
+            | Instruction::LoadFlags { .. } => mode != Mode::Chip8,
+            Instruction::ScrollUp { .. }
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LoadLongIndex
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudioPattern
+            | Instruction::SetPitch { .. } => mode == Mode::XoChip,
+            _ => true,
+        }
+    }
+
+    // the mnemonic of what the machine runs with `quirks`, BNNN is BXNN with the jump quirk
+    pub fn mnemonic(self, quirks: Quirks) -> String {
+        match self {
+            Instruction::JumpWithOffset { x, nnn } if quirks.jump_uses_vx => {
+                format!("JP V{:X}, {:#05X}", x, nnn)
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub op_code: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid op code {:#06x}", self.op_code)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(op_code: u16) -> Result<Instruction, DecodeError> {
+    let x = ((0x0F00u16 & op_code) >> 8) as u8;
+    let y = ((0x00F0u16 & op_code) >> 4) as u8;
+    let n = (0x000Fu16 & op_code) as u8;
+    let nn = (0x00FFu16 & op_code) as u8;
+    let nnn = 0x0FFFu16 & op_code;
+
+    let instruction = match (op_code & 0xF000, n) {
+        (0x0000, _) => match op_code {
+            0x00C0..=0x00CF => Instruction::ScrollDown { n },
+            0x00D0..=0x00DF => Instruction::ScrollUp { n },
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowRes,
+            0x00FF => Instruction::HighRes,
+            _ => return Err(DecodeError { op_code }),
+        },
+        (0x1000, _) => Instruction::Jump { nnn },
+        (0x2000, _) => Instruction::Call { nnn },
+        (0x3000, _) => Instruction::SkipIfEqImm { x, nn },
+        (0x4000, _) => Instruction::SkipIfNeqImm { x, nn },
+        (0x5000, 0x0) => Instruction::SkipIfEq { x, y },
+        (0x5000, 0x2) => Instruction::SaveRange { x, y },
+        (0x5000, 0x3) => Instruction::LoadRange { x, y },
+        (0x6000, _) => Instruction::SetImm { x, nn },
+        (0x7000, _) => Instruction::AddImm { x, nn },
+        (0x8000, 0x0) => Instruction::Set { x, y },
+        (0x8000, 0x1) => Instruction::Or { x, y },
+        (0x8000, 0x2) => Instruction::And { x, y },
+        (0x8000, 0x3) => Instruction::Xor { x, y },
+        (0x8000, 0x4) => Instruction::Add { x, y },
+        (0x8000, 0x5) => Instruction::SubXY { x, y },
+        (0x8000, 0x6) => Instruction::ShiftRight { x, y },
+        (0x8000, 0x7) => Instruction::SubYX { x, y },
+        (0x8000, 0xE) => Instruction::ShiftLeft { x, y },
+        (0x9000, 0x0) => Instruction::SkipIfNeq { x, y },
+        (0xA000, _) => Instruction::SetIndex { nnn },
+        (0xB000, _) => Instruction::JumpWithOffset { x, nnn },
+        (0xC000, _) => Instruction::Random { x, nn },
+        (0xD000, _) => Instruction::DrawSprite { x, y, n },
+        (0xE000, _) => match nn {
+            0x9E => Instruction::SkipIfKey { x },
+            0xA1 => Instruction::SkipIfNotKey { x },
+            _ => return Err(DecodeError { op_code }),
+        },
+        (0xF000, _) => match nn {
+            0x00 if x == 0 => Instruction::LoadLongIndex,
+            0x01 => Instruction::SelectPlanes { x },
+            0x02 if x == 0 => Instruction::LoadAudioPattern,
+            0x07 => Instruction::GetDelay { x },
+            0x0A => Instruction::WaitForKey { x },
+            0x15 => Instruction::SetDelay { x },
+            0x18 => Instruction::SetSound { x },
+            0x1E => Instruction::AddToIndex { x },
+            0x29 => Instruction::FontChar { x },
+            0x30 => Instruction::BigFontChar { x },
+            0x3A => Instruction::SetPitch { x },
+            0x33 => Instruction::ToDigits { x },
+            0x55 => Instruction::StoreRegisters { x },
+            0x65 => Instruction::LoadRegisters { x },
+            0x75 => Instruction::StoreFlags { x },
+            0x85 => Instruction::LoadFlags { x },
+            _ => return Err(DecodeError { op_code }),
+        },
+        _ => return Err(DecodeError { op_code }),
+    };
+    Ok(instruction)
+}
+
+// inverse of decode, LoadLongIndex is only the F000 word without its address
+pub fn encode(instruction: Instruction) -> u16 {
+    let vx = |x: u8| ((x & 0xF) as u16) << 8;
+    let vxy = |x: u8, y: u8| vx(x) | ((y & 0xF) as u16) << 4;
+    match instruction {
+        Instruction::ScrollDown { n } => 0x00C0 | (n & 0xF) as u16,
+        Instruction::ScrollUp { n } => 0x00D0 | (n & 0xF) as u16,
+        Instruction::ClearScreen => 0x00E0,
+        Instruction::Return => 0x00EE,
+        Instruction::ScrollRight => 0x00FB,
+        Instruction::ScrollLeft => 0x00FC,
+        Instruction::Exit => 0x00FD,
+        Instruction::LowRes => 0x00FE,
+        Instruction::HighRes => 0x00FF,
+        Instruction::LoadLongIndex => 0xF000,
+        Instruction::SelectPlanes { x } => 0xF001 | vx(x),
+        Instruction::LoadAudioPattern => 0xF002,
+        Instruction::Jump { nnn } => 0x1000 | nnn & 0xFFF,
+        Instruction::Call { nnn } => 0x2000 | nnn & 0xFFF,
+        Instruction::SkipIfEqImm { x, nn } => 0x3000 | vx(x) | nn as u16,
+        Instruction::SkipIfNeqImm { x, nn } => 0x4000 | vx(x) | nn as u16,
+        Instruction::SkipIfEq { x, y } => 0x5000 | vxy(x, y),
+        Instruction::SaveRange { x, y } => 0x5002 | vxy(x, y),
+        Instruction::LoadRange { x, y } => 0x5003 | vxy(x, y),
+        Instruction::SetImm { x, nn } => 0x6000 | vx(x) | nn as u16,
+        Instruction::AddImm { x, nn } => 0x7000 | vx(x) | nn as u16,
+        Instruction::Set { x, y } => 0x8000 | vxy(x, y),
+        Instruction::Or { x, y } => 0x8001 | vxy(x, y),
+        Instruction::And { x, y } => 0x8002 | vxy(x, y),
+        Instruction::Xor { x, y } => 0x8003 | vxy(x, y),
+        Instruction::Add { x, y } => 0x8004 | vxy(x, y),
+        Instruction::SubXY { x, y } => 0x8005 | vxy(x, y),
+        Instruction::ShiftRight { x, y } => 0x8006 | vxy(x, y),
+        Instruction::SubYX { x, y } => 0x8007 | vxy(x, y),
+        Instruction::ShiftLeft { x, y } => 0x800E | vxy(x, y),
+        Instruction::SkipIfNeq { x, y } => 0x9000 | vxy(x, y),
+        Instruction::SetIndex { nnn } => 0xA000 | nnn & 0xFFF,
+        Instruction::JumpWithOffset { nnn, .. } => 0xB000 | nnn & 0xFFF,
+        Instruction::Random { x, nn } => 0xC000 | vx(x) | nn as u16,
+        Instruction::DrawSprite { x, y, n } => 0xD000 | vxy(x, y) | (n & 0xF) as u16,
+        Instruction::SkipIfKey { x } => 0xE09E | vx(x),
+        Instruction::SkipIfNotKey { x } => 0xE0A1 | vx(x),
+        Instruction::GetDelay { x } => 0xF007 | vx(x),
+        Instruction::WaitForKey { x } => 0xF00A | vx(x),
+        Instruction::SetDelay { x } => 0xF015 | vx(x),
+        Instruction::SetSound { x } => 0xF018 | vx(x),
+        Instruction::AddToIndex { x } => 0xF01E | vx(x),
+        Instruction::FontChar { x } => 0xF029 | vx(x),
+        Instruction::BigFontChar { x } => 0xF030 | vx(x),
+        Instruction::ToDigits { x } => 0xF033 | vx(x),
+        Instruction::StoreRegisters { x } => 0xF055 | vx(x),
+        Instruction::LoadRegisters { x } => 0xF065 | vx(x),
+        Instruction::StoreFlags { x } => 0xF075 | vx(x),
+        Instruction::LoadFlags { x } => 0xF085 | vx(x),
+        Instruction::SetPitch { x } => 0xF03A | vx(x),
+    }
+}
+
+// Mnemonics in the classic Cowgod notation, e.g. "DRW V0, V1, 5"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::LoadLongIndex => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes { x } => write!(f, "PLANE {}", x),
+            Instruction::LoadAudioPattern => write!(f, "AUDIO"),
+            Instruction::Jump { nnn } => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SkipIfEqImm { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfNeqImm { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfEq { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::SetImm { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddImm { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::Set { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubXY { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubYX { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfNeq { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIndex { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JumpWithOffset { nnn, .. } => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Random { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::DrawSprite { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfKey { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::GetDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddToIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::FontChar { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFontChar { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::ToDigits { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::SetPitch { x } => write!(f, "PITCH V{:X}", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_inverts_decode_for_every_op_code() {
+        for op_code in 0..=u16::MAX {
+            if let Ok(instruction) = decode(op_code) {
+                assert_eq!(encode(instruction), op_code, "{:#06x}", op_code);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_inverts_encode_for_every_form() {
+        let instructions = [
+            Instruction::ScrollDown { n: 4 },
+            Instruction::ScrollUp { n: 2 },
+            Instruction::ClearScreen,
+            Instruction::Return,
+            Instruction::ScrollRight,
+            Instruction::ScrollLeft,
+            Instruction::Exit,
+            Instruction::LowRes,
+            Instruction::HighRes,
+            Instruction::LoadLongIndex,
+            Instruction::SelectPlanes { x: 3 },
+            Instruction::LoadAudioPattern,
+            Instruction::Jump { nnn: 0x2A0 },
+            Instruction::Call { nnn: 0xFFF },
+            Instruction::SkipIfEqImm { x: 1, nn: 0x12 },
+            Instruction::SkipIfNeqImm { x: 2, nn: 0xFF },
+            Instruction::SkipIfEq { x: 3, y: 4 },
+            Instruction::SaveRange { x: 0, y: 0xF },
+            Instruction::LoadRange { x: 0xF, y: 0 },
+            Instruction::SetImm { x: 5, nn: 0x00 },
+            Instruction::AddImm { x: 6, nn: 0x80 },
+            Instruction::Set { x: 7, y: 8 },
+            Instruction::Or { x: 9, y: 0xA },
+            Instruction::And { x: 0xB, y: 0xC },
+            Instruction::Xor { x: 0xD, y: 0xE },
+            Instruction::Add { x: 0xF, y: 0 },
+            Instruction::SubXY { x: 1, y: 2 },
+            Instruction::ShiftRight { x: 3, y: 4 },
+            Instruction::SubYX { x: 5, y: 6 },
+            Instruction::ShiftLeft { x: 7, y: 8 },
+            Instruction::SkipIfNeq { x: 9, y: 0xA },
+            Instruction::SetIndex { nnn: 0x123 },
+            Instruction::JumpWithOffset { x: 4, nnn: 0x456 },
+            Instruction::Random { x: 0xB, nn: 0x0F },
+            Instruction::DrawSprite { x: 0, y: 1, n: 0 },
+            Instruction::DrawSprite { x: 2, y: 3, n: 0xF },
+            Instruction::SkipIfKey { x: 4 },
+            Instruction::SkipIfNotKey { x: 5 },
+            Instruction::GetDelay { x: 6 },
+            Instruction::WaitForKey { x: 7 },
+            Instruction::SetDelay { x: 8 },
+            Instruction::SetSound { x: 9 },
+            Instruction::AddToIndex { x: 0xA },
+            Instruction::FontChar { x: 0xB },
+            Instruction::BigFontChar { x: 0xC },
+            Instruction::ToDigits { x: 0xD },
+            Instruction::StoreRegisters { x: 0xE },
+            Instruction::LoadRegisters { x: 0xF },
+            Instruction::StoreFlags { x: 0 },
+            Instruction::LoadFlags { x: 1 },
+            Instruction::SetPitch { x: 2 },
+        ];
+        for instruction in instructions {
+            assert_eq!(decode(encode(instruction)), Ok(instruction));
+        }
+    }
+
+    #[test]
+    fn invalid_op_codes_are_decode_errors() {
+        for op_code in [
+            0x0000, 0x0123, 0x00E1, 0x5001, 0x5124, 0x8008, 0x800F, 0x9001, 0xE000, 0xE19F, 0xF0FF,
+            0xF100, 0xF102, 0xF066,
+        ] {
+            assert_eq!(decode(op_code), Err(DecodeError { op_code }));
+        }
+    }
+
+    #[test]
+    fn offset_jumps_name_their_register_with_the_jump_quirk() {
+        let jump = Instruction::JumpWithOffset { x: 4, nnn: 0x456 };
+        assert_eq!(jump.to_string(), "JP V0, 0x456");
+        assert_eq!(
+            jump.mnemonic(Quirks::preset("cosmac-vip").unwrap()),
+            "JP V0, 0x456"
+        );
+        assert_eq!(
+            jump.mnemonic(Quirks::preset("chip48").unwrap()),
+            "JP V4, 0x456"
+        );
+        let draw = Instruction::DrawSprite { x: 1, y: 2, n: 3 };
+        assert_eq!(draw.mnemonic(Quirks::default()), draw.to_string());
+    }
+}
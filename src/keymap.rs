@@ -0,0 +1,247 @@
+use crate::error::Chip8Error;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const KEYMAP_PRESETS: [&str; 4] = ["qwerty", "qwertz", "azerty", "numpad"];
+
+// names of the SFML keys as printed by their Debug implementation, separated by spaces
+pub const KEY_NAMES: &str = "\
+    A B C D E F G H I J K L M N O P Q R S T U V W X Y Z \
+    Num0 Num1 Num2 Num3 Num4 Num5 Num6 Num7 Num8 Num9 \
+    Escape LControl LShift LAlt LSystem RControl RShift RAlt RSystem Menu \
+    LBracket RBracket Semicolon Comma Period Quote Slash Backslash Tilde Equal Hyphen \
+    Space Enter Backspace Tab PageUp PageDown End Home Insert Delete \
+    Add Subtract Multiply Divide Left Right Up Down \
+    Numpad0 Numpad1 Numpad2 Numpad3 Numpad4 Numpad5 Numpad6 Numpad7 Numpad8 Numpad9 \
+    F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12 F13 F14 F15 Pause";
+
+pub fn is_key_name(name: &str) -> bool {
+    KEY_NAMES.split_whitespace().any(|known| known == name)
+}
+
+/*
+Keymap:
+    - which host key presses which of the 16 CHIP-8 keys
+    keys:
+        - SFML key name => CHIP-8 key (0x0-0xF), several keys may press the same CHIP-8 key
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: BTreeMap<String, u8>,
+}
+
+/*
+KeymapFile:
+    - a keymap in TOML:
+        preset = "azerty"          # layout the bindings start from, qwertz if left out
+        [keys]
+        Up = 0x5                   # SFML key name = CHIP-8 key
+        [roms.pong]
+        Space = 0xA                # only for roms named pong.*
+*/
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    preset: Option<String>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    #[serde(default)]
+    roms: BTreeMap<String, BTreeMap<String, u8>>,
+}
+
+impl Keymap {
+    // the hex keypad is laid out on the left of the keyboard:
+    // 1 2 3 C
+    // 4 5 6 D
+    // 7 8 9 E
+    // A 0 B F
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let rows: [[&str; 4]; 4] = match name {
+            "qwerty" => [
+                ["Num1", "Num2", "Num3", "Num4"],
+                ["Q", "W", "E", "R"],
+                ["A", "S", "D", "F"],
+                ["Z", "X", "C", "V"],
+            ],
+            "qwertz" => [
+                ["Num1", "Num2", "Num3", "Num4"],
+                ["Q", "W", "E", "R"],
+                ["A", "S", "D", "F"],
+                ["Y", "X", "C", "V"],
+            ],
+            "azerty" => [
+                ["Num1", "Num2", "Num3", "Num4"],
+                ["A", "Z", "E", "R"],
+                ["Q", "S", "D", "F"],
+                ["W", "X", "C", "V"],
+            ],
+            // digits on their numpad keys, A-F on the keys around it
+            "numpad" => [
+                ["Numpad1", "Numpad2", "Numpad3", "Subtract"],
+                ["Numpad4", "Numpad5", "Numpad6", "Add"],
+                ["Numpad7", "Numpad8", "Numpad9", "Enter"],
+                ["Divide", "Numpad0", "Multiply", "Delete"],
+            ],
+            _ => return None,
+        };
+        let layout = [
+            [0x1, 0x2, 0x3, 0xC],
+            [0x4, 0x5, 0x6, 0xD],
+            [0x7, 0x8, 0x9, 0xE],
+            [0xA, 0x0, 0xB, 0xF],
+        ];
+        let keys = rows
+            .iter()
+            .flatten()
+            .zip(layout.iter().flatten())
+            .map(|(name, key)| (name.to_string(), *key))
+            .collect();
+        Some(Keymap { keys })
+    }
+
+    // a keymap file, with the overrides for `rom` (file name without extension) applied
+    pub fn load(path: &Path, rom: &str) -> Result<Keymap, Chip8Error> {
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Keymap::from_toml(&text, rom))
+            .map_err(|message| Chip8Error::Config {
+                message: format!("{}: {}", path.display(), message),
+            })
+    }
+
+    pub fn from_toml(text: &str, rom: &str) -> Result<Keymap, String> {
+        let file: KeymapFile = toml::from_str(text).map_err(|err| err.to_string())?;
+        let preset = file.preset.as_deref().unwrap_or("qwertz");
+        let mut keymap = Keymap::preset(preset).ok_or_else(|| {
+            format!(
+                "unknown preset: {}, expected one of {}",
+                preset,
+                KEYMAP_PRESETS.join(", ")
+            )
+        })?;
+        keymap.bind_all(&file.keys)?;
+        if let Some(overrides) = file.roms.get(rom) {
+            keymap.bind_all(overrides)?;
+        }
+        Ok(keymap)
+    }
+
+    // a preset name or the path of a keymap file
+    pub fn from_arg(arg: &str, rom: &str) -> Result<Keymap, Chip8Error> {
+        match Keymap::preset(arg) {
+            Some(keymap) => Ok(keymap),
+            None => Keymap::load(Path::new(arg), rom),
+        }
+    }
+
+    pub fn bind(&mut self, name: &str, key: u8) -> Result<(), String> {
+        if !is_key_name(name) {
+            return Err(format!("unknown key name: {}", name));
+        }
+        if key > 0xF {
+            return Err(format!(
+                "{} is bound to {:#x}, keys go from 0x0 to 0xF",
+                name, key
+            ));
+        }
+        self.keys.insert(name.to_string(), key);
+        Ok(())
+    }
+
+    fn bind_all(&mut self, keys: &BTreeMap<String, u8>) -> Result<(), String> {
+        keys.iter()
+            .try_for_each(|(name, key)| self.bind(name, *key))
+    }
+
+    // CHIP-8 key pressed by the host key `name`
+    pub fn key(&self, name: &str) -> Option<u8> {
+        self.keys.get(name).copied()
+    }
+
+    pub fn bindings(&self) -> &BTreeMap<String, u8> {
+        &self.keys
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset("qwertz").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        preset = "azerty"
+        [keys]
+        Up = 0x5
+        [roms.pong]
+        Space = 0xA
+        Q = 0x0
+    "#;
+
+    // the overrides are picked by the file name of the rom without its extension
+    fn keymap_for(rom_path: &str) -> Keymap {
+        let rom = Path::new(rom_path).file_stem().unwrap().to_string_lossy();
+        Keymap::from_toml(FILE, &rom).unwrap()
+    }
+
+    #[test]
+    fn presets_follow_the_keyboard_layout() {
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!(azerty.key("A"), Some(0x4));
+        assert_eq!(azerty.key("Z"), Some(0x5));
+        assert_eq!(azerty.key("Q"), Some(0x7));
+        assert_eq!(azerty.key("W"), Some(0xA));
+        let qwertz = Keymap::preset("qwertz").unwrap();
+        assert_eq!(qwertz.key("Y"), Some(0xA));
+        assert_eq!(qwertz.key("Z"), None);
+        assert_eq!(qwertz, Keymap::default());
+        for preset in KEYMAP_PRESETS {
+            assert_eq!(Keymap::preset(preset).unwrap().bindings().len(), 16);
+        }
+        assert_eq!(Keymap::preset("dvorak"), None);
+    }
+
+    #[test]
+    fn files_bind_keys_on_top_of_their_preset() {
+        let keymap = keymap_for("tetris.ch8");
+        assert_eq!(keymap.key("Up"), Some(0x5));
+        assert_eq!(keymap.key("Z"), Some(0x5));
+        assert_eq!(keymap.key("Q"), Some(0x7));
+        assert_eq!(keymap.key("Space"), None);
+        // without a preset the bindings start from qwertz
+        let keymap = Keymap::from_toml("[keys]\nUp = 0x5", "pong").unwrap();
+        assert_eq!(keymap.key("Y"), Some(0xA));
+    }
+
+    #[test]
+    fn rom_overrides_apply_only_to_their_rom() {
+        let pong = keymap_for("roms/pong.ch8");
+        assert_eq!(pong.key("Space"), Some(0xA));
+        assert_eq!(pong.key("Q"), Some(0x0));
+        assert_eq!(pong.key("Up"), Some(0x5));
+        assert_eq!(keymap_for("pong.8o"), pong);
+        for other in ["pong2.ch8", "tetris.ch8", "Pong.ch8"] {
+            let keymap = keymap_for(other);
+            assert_eq!(keymap.key("Space"), None, "{}", other);
+            assert_eq!(keymap.key("Q"), Some(0x7), "{}", other);
+        }
+    }
+
+    #[test]
+    fn unknown_keys_and_values_are_rejected() {
+        let err = Keymap::from_toml("[keys]\nUpArrow = 0x5", "pong").unwrap_err();
+        assert_eq!(err, "unknown key name: UpArrow");
+        let err = Keymap::from_toml("[keys]\nUp = 0x10", "pong").unwrap_err();
+        assert_eq!(err, "Up is bound to 0x10, keys go from 0x0 to 0xF");
+        // also in the overrides of the loaded rom
+        assert!(Keymap::from_toml("[roms.pong]\nSpace = 0x11", "pong").is_err());
+        assert!(Keymap::from_toml("preset = \"dvorak\"", "pong").is_err());
+        assert!(Keymap::from_toml("keys = 1", "pong").is_err());
+    }
+}
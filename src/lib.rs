@@ -0,0 +1,29 @@
+pub mod assembler;
+pub mod audio;
+pub mod config;
+pub mod database;
+pub mod debugger;
+pub mod disassembler;
+pub mod error;
+pub mod framebuffer;
+pub mod frontend;
+pub mod image;
+pub mod instruction;
+pub mod keymap;
+mod machine;
+pub mod memory;
+pub mod mode;
+pub mod movie;
+pub mod palette;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod state;
+pub mod timing;
+pub mod trace;
+pub mod video;
+
+pub use error::Chip8Error;
+pub use machine::Chip8;
+pub use mode::Mode;
+pub use quirks::Quirks;
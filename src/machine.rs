@@ -0,0 +1,1080 @@
+use crate::audio::{Sound, Synth};
+use crate::error::{Chip8Error, Fault};
+use crate::framebuffer::Framebuffer;
+use crate::frontend::{Display, Keypad, Speaker};
+use crate::instruction::{decode, Instruction};
+use crate::memory::{Memory, BIG_FONT_ADDRESS, FONT_ADDRESS, RAM_SIZE, XO_RAM_SIZE};
+use crate::mode::Mode;
+use crate::quirks::Quirks;
+use crate::random::Random;
+use crate::state::{invalid, StateReader, StateWriter};
+use crate::timing::{vip_cycles, Scheduler, VIP_CYCLES_PER_FRAME, VIP_DISPLAY_CYCLES};
+use std::fs;
+
+/*
+Chip8:
+    memory:
+        - ram, registers, stack and timers
+    framebuffer:
+        - 64x32 (or 128x64 SUPER-CHIP) pixel state with two XO-CHIP bitplanes
+    key_flags:
+        - stores key state of every key (bit n => key n)
+    mode:
+        - instruction set to run (CHIP-8, SUPER-CHIP or XO-CHIP)
+    quirks:
+        - platform specific behaviour of ambiguous instructions
+    exited:
+        - set by the SUPER-CHIP 00FD exit instruction
+    vblank_wait:
+        - set by DXYN with the display wait quirk, cleared by the next timer tick
+    beeping:
+        - the sound timer was running during the last frame, latched by the timer tick
+          so a sound timer of n beeps for n whole frames
+    instruction_count:
+        - instructions executed since the machine was created
+    frame_progress:
+        - instructions (or COSMAC VIP machine cycles) executed in the current frame
+    random:
+        - generator of the CXNN random numbers, randomly seeded unless set_seed is called
+*/
+pub struct Chip8 {
+    memory: Memory,
+    framebuffer: Framebuffer,
+    key_flags: u16,
+    mode: Mode,
+    quirks: Quirks,
+    exited: bool,
+    vblank_wait: bool,
+    beeping: bool,
+    instruction_count: u64,
+    frame_progress: u32,
+    random: Random,
+}
+
+impl Chip8 {
+    pub fn new(mode: Mode, quirks: Quirks) -> Self {
+        let memory = match mode {
+            Mode::XoChip => Memory::with_size(XO_RAM_SIZE),
+            _ => Memory::new(),
+        };
+        Chip8 {
+            memory,
+            framebuffer: Framebuffer::new(),
+            key_flags: 0,
+            mode,
+            quirks,
+            exited: false,
+            vblank_wait: false,
+            beeping: false,
+            instruction_count: 0,
+            frame_progress: 0,
+            random: Random::new(),
+        }
+    }
+
+    pub fn load_rom(&mut self, path: &str) -> Result<(), Chip8Error> {
+        let rom = fs::read(path)?;
+        self.load_rom_bytes(&rom)
+    }
+
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let pc = self.memory.pc();
+        let capacity = self.memory.ram_size() - pc as usize;
+        if rom.len() > capacity {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                capacity,
+            });
+        }
+        self.memory
+            .write_ram(pc, rom)
+            .map_err(|fault| Chip8Error::from_fault(fault, pc, 0))
+    }
+
+    // fetch, decode and execute a single instruction
+    // does nothing once exited or while waiting for the next frame
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        self.step_timed(false).map(|_| ())
+    }
+
+    // step, returning the COSMAC VIP machine cycles taken if `vip_timing` is set
+    fn step_timed(&mut self, vip_timing: bool) -> Result<u32, Chip8Error> {
+        if self.waiting() {
+            return Ok(0);
+        }
+        let pc = self.memory.pc();
+        let op_code =
+            fetch(&mut self.memory).map_err(|fault| Chip8Error::from_fault(fault, pc, 0))?;
+        let instruction = match decode(op_code) {
+            Ok(instruction) if instruction.supported_by(self.mode) => instruction,
+            _ => return Err(Chip8Error::from_fault(Fault::UnknownOpcode, pc, op_code)),
+        };
+        let cycles = if vip_timing {
+            vip_cycles(instruction, &self.memory, self.key_flags)
+        } else {
+            0
+        };
+        self.execute(instruction)
+            .map_err(|fault| Chip8Error::from_fault(fault, pc, op_code))?;
+        self.instruction_count += 1;
+        Ok(cycles)
+    }
+
+    // execute `instructions` instructions followed by a single 60Hz timer tick
+    pub fn run_frame(&mut self, instructions: u32) -> Result<(), Chip8Error> {
+        self.run_scheduled_frame(Scheduler::Instructions(instructions))
+    }
+
+    // execute a frame worth of COSMAC VIP machine cycles followed by a single 60Hz timer tick
+    pub fn run_vip_frame(&mut self) -> Result<(), Chip8Error> {
+        self.run_scheduled_frame(Scheduler::VipCycles)
+    }
+
+    pub fn run_scheduled_frame(&mut self, scheduler: Scheduler) -> Result<(), Chip8Error> {
+        while !self.step_in_frame(scheduler)? {}
+        Ok(())
+    }
+
+    // execute the next instruction of the current frame,
+    // returns true once the frame is over and the timers ticked
+    // waiting for the display interrupt gives up the rest of the frame
+    pub fn step_in_frame(&mut self, scheduler: Scheduler) -> Result<bool, Chip8Error> {
+        let (budget, vip_timing) = match scheduler {
+            Scheduler::Instructions(instructions) => (instructions, false),
+            Scheduler::VipCycles => (VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES, true),
+        };
+        if self.frame_progress < budget && !self.waiting() {
+            let cycles = self.step_timed(vip_timing)?;
+            self.frame_progress += if vip_timing { cycles } else { 1 };
+        }
+        if self.frame_progress < budget && !self.waiting() {
+            return Ok(false);
+        }
+        // cycles the last instruction ran over are taken from the next frame
+        self.frame_progress = if vip_timing && !self.vblank_wait {
+            self.frame_progress.saturating_sub(budget)
+        } else {
+            0
+        };
+        self.update_timers();
+        Ok(true)
+    }
+
+    // no instruction runs until the next frame (or ever, once exited)
+    pub fn waiting(&self) -> bool {
+        self.exited || self.vblank_wait
+    }
+
+    pub fn update_timers(&mut self) {
+        self.vblank_wait = false;
+        self.beeping = self.memory.sound_register() > 0;
+        self.memory.decrement_delay();
+        self.memory.decrement_sound();
+    }
+
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    // true if the framebuffer changed since the last call
+    pub fn take_redraw(&mut self) -> bool {
+        self.framebuffer.take_dirty()
+    }
+
+    // present the framebuffer if it changed since the last presentation
+    pub fn present(&mut self, display: &mut impl Display) {
+        if self.take_redraw() {
+            display.present(&self.framebuffer);
+        }
+    }
+
+    // poll the keypad for events and latch its key state
+    pub fn poll_keypad(&mut self, keypad: &mut impl Keypad) {
+        keypad.handle_events();
+        self.key_flags = keypad.key_flags();
+    }
+
+    // the beeper sounds while the sound timer is non-zero
+    pub fn sound_playing(&self) -> bool {
+        self.beeping
+    }
+
+    // XO-CHIP programs that loaded an audio pattern play the pattern instead of the beep
+    pub fn sound(&self) -> Sound {
+        let pattern = self.memory.audio_pattern();
+        if !self.sound_playing() {
+            Sound::Silence
+        } else if self.mode == Mode::XoChip && pattern.iter().any(|byte| *byte != 0) {
+            Sound::Pattern {
+                pattern: *pattern,
+                rate: self.memory.playback_rate(),
+            }
+        } else {
+            Sound::Beep
+        }
+    }
+
+    pub fn play_sound(&self, speaker: &mut impl Speaker) {
+        speaker.play(self.sound());
+    }
+
+    pub fn render_audio(&self, synth: &mut Synth, samples: &mut [i16]) {
+        synth.play(self.sound(), samples);
+    }
+
+    pub fn key_flags(&self) -> u16 {
+        self.key_flags
+    }
+
+    // versioned and checksummed snapshot of the whole machine
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u8(self.mode as u8);
+        let quirks = self.quirks;
+        for quirk in [
+            quirks.shift_uses_vy,
+            quirks.increment_index,
+            quirks.jump_uses_vx,
+            quirks.vf_reset,
+            quirks.clip_sprites,
+            quirks.display_wait,
+            quirks.index_overflow,
+        ] {
+            writer.bool(quirk);
+        }
+        self.memory.write_state(&mut writer);
+        self.framebuffer.write_state(&mut writer);
+        writer.u16(self.key_flags);
+        writer.bool(self.exited);
+        writer.bool(self.vblank_wait);
+        writer.bool(self.beeping);
+        writer.u64(self.instruction_count);
+        writer.u32(self.frame_progress);
+        writer.u64(self.random.state());
+        writer.finish()
+    }
+
+    // replace the machine with a save state, mode and quirks included
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = StateReader::new(state)?;
+        let mode = match reader.u8()? {
+            0 => Mode::Chip8,
+            1 => Mode::SuperChip,
+            2 => Mode::XoChip,
+            mode => return Err(invalid(format!("unknown mode {}", mode))),
+        };
+        let quirks = Quirks {
+            shift_uses_vy: reader.bool()?,
+            increment_index: reader.bool()?,
+            jump_uses_vx: reader.bool()?,
+            vf_reset: reader.bool()?,
+            clip_sprites: reader.bool()?,
+            display_wait: reader.bool()?,
+            index_overflow: reader.bool()?,
+        };
+        let mut memory = Memory::read_state(&mut reader)?;
+        // watchpoints keep working across loads and rewinds
+        memory.set_access_logging(self.memory.access_logging());
+        let expected_ram = match mode {
+            Mode::XoChip => XO_RAM_SIZE,
+            _ => RAM_SIZE,
+        };
+        if memory.ram_size() != expected_ram {
+            return Err(invalid(format!(
+                "{} bytes of ram don't match mode {}",
+                memory.ram_size(),
+                mode
+            )));
+        }
+        *self = Chip8 {
+            memory,
+            framebuffer: Framebuffer::read_state(&mut reader)?,
+            key_flags: reader.u16()?,
+            mode,
+            quirks,
+            exited: reader.bool()?,
+            vblank_wait: reader.bool()?,
+            beeping: reader.bool()?,
+            instruction_count: reader.u64()?,
+            frame_progress: reader.u32()?,
+            random: Random::from_state(reader.u64()?),
+        };
+        reader.finish()
+    }
+
+    // makes the CXNN random numbers repeat from run to run
+    pub fn set_seed(&mut self, seed: u32) {
+        self.random = Random::with_seed(seed)
+    }
+
+    pub fn set_key_flags(&mut self, key_flags: u16) {
+        self.key_flags = key_flags
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if pressed {
+            self.key_flags |= 1u16 << (key & 0xF);
+        } else {
+            self.key_flags &= !(1u16 << (key & 0xF));
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Fault> {
+        let memory = &mut self.memory;
+        let framebuffer = &mut self.framebuffer;
+        let key_flags = self.key_flags;
+        let quirks = self.quirks;
+        let wide_sprites = self.mode != Mode::Chip8;
+
+        match instruction {
+            Instruction::ScrollDown { n } => framebuffer.scroll_down(n as usize),
+            Instruction::ScrollUp { n } => framebuffer.scroll_up(n as usize),
+            Instruction::ClearScreen => framebuffer.clear(),
+            Instruction::Return => {
+                let adress = memory.pop_stack()?;
+                memory.jump_pc(adress)
+            }
+            Instruction::ScrollRight => framebuffer.scroll_right(4),
+            Instruction::ScrollLeft => framebuffer.scroll_left(4),
+            Instruction::Exit => self.exited = true,
+            Instruction::LowRes => framebuffer.set_hires(false),
+            Instruction::HighRes => framebuffer.set_hires(true),
+            Instruction::LoadLongIndex => {
+                let (high, low) = memory.fetch_instruction()?;
+                memory.set_index_register((high as u16) << 8 | low as u16);
+                memory.increment_pc()
+            }
+            Instruction::SelectPlanes { x } => framebuffer.select_planes(x),
+            Instruction::LoadAudioPattern => load_audio_pattern(memory)?,
+            Instruction::Jump { nnn } => memory.jump_pc(nnn),
+            Instruction::Call { nnn } => call_subroutine(nnn, memory)?,
+            Instruction::SkipIfEqImm { x, nn } => skip_if_eq_im(x, nn, memory)?,
+            Instruction::SkipIfNeqImm { x, nn } => skip_if_neq_im(x, nn, memory)?,
+            Instruction::SkipIfEq { x, y } => skip_if_eq(x, y, memory)?,
+            Instruction::SaveRange { x, y } => save_range(memory, x, y)?,
+            Instruction::LoadRange { x, y } => load_range(memory, x, y)?,
+            Instruction::SetImm { x, nn } => memory.set_var_register(x, nn)?,
+            Instruction::AddImm { x, nn } => add_var_register(memory, x, nn)?,
+            Instruction::Set { x, y } => {
+                let vy = memory.get_var_register(y)?;
+                memory.set_var_register(x, vy)?
+            }
+            Instruction::Or { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                memory.set_var_register(x, vx | vy)?;
+                reset_vf(memory, quirks)?
+            }
+            Instruction::And { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                memory.set_var_register(x, vx & vy)?;
+                reset_vf(memory, quirks)?
+            }
+            Instruction::Xor { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                memory.set_var_register(x, vx ^ vy)?;
+                reset_vf(memory, quirks)?
+            }
+            Instruction::Add { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                add(memory, x, vx, vy)?
+            }
+            Instruction::SubXY { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                sub_x_y(memory, x, vx, vy)?
+            }
+            Instruction::ShiftRight { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                shift_right(memory, x, vx, vy, quirks.shift_uses_vy)?
+            }
+            Instruction::SubYX { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                sub_y_x(memory, x, vx, vy)?
+            }
+            Instruction::ShiftLeft { x, y } => {
+                let (vx, vy) = var_registers(memory, x, y)?;
+                shift_left(memory, x, vx, vy, quirks.shift_uses_vy)?
+            }
+            Instruction::SkipIfNeq { x, y } => skip_if_neq(x, y, memory)?,
+            Instruction::SetIndex { nnn } => memory.set_index_register(nnn),
+            Instruction::JumpWithOffset { x, nnn } => {
+                jump_with_offset(memory, x, nnn, quirks.jump_uses_vx)?
+            }
+            Instruction::Random { x, nn } => memory.set_var_register(x, self.random.byte() & nn)?,
+            Instruction::DrawSprite { x, y, n } => {
+                draw_sprite(
+                    x,
+                    y,
+                    n,
+                    wide_sprites,
+                    quirks.clip_sprites,
+                    memory,
+                    framebuffer,
+                )?;
+                self.vblank_wait = quirks.display_wait
+            }
+            Instruction::SkipIfKey { x } => skip_if_key(x, true, memory, key_flags)?,
+            Instruction::SkipIfNotKey { x } => skip_if_key(x, false, memory, key_flags)?,
+            Instruction::GetDelay { x } => {
+                let delay_timer = memory.delay_register();
+                memory.set_var_register(x, delay_timer)?
+            }
+            Instruction::WaitForKey { x } => wait_for_keyinput(memory, key_flags, x)?,
+            Instruction::SetDelay { x } => {
+                let vx = memory.get_var_register(x)?;
+                memory.set_delay_register(vx)
+            }
+            Instruction::SetSound { x } => {
+                let vx = memory.get_var_register(x)?;
+                memory.set_sounds_register(vx)
+            }
+            Instruction::AddToIndex { x } => {
+                let vx = memory.get_var_register(x)?;
+                let overflow_flag = quirks.index_overflow && self.mode != Mode::XoChip;
+                add_to_index(memory, vx, overflow_flag)?
+            }
+            Instruction::FontChar { x } => {
+                let vx = memory.get_var_register(x)?;
+                get_font_char(memory, vx)
+            }
+            Instruction::BigFontChar { x } => {
+                let vx = memory.get_var_register(x)?;
+                get_big_font_char(memory, vx)
+            }
+            Instruction::ToDigits { x } => {
+                let vx = memory.get_var_register(x)?;
+                to_digits(memory, vx)?
+            }
+            Instruction::StoreRegisters { x } => {
+                store_registers(memory, x, quirks.increment_index)?
+            }
+            Instruction::LoadRegisters { x } => load_registers(memory, x, quirks.increment_index)?,
+            Instruction::StoreFlags { x } => store_flags(memory, x)?,
+            Instruction::LoadFlags { x } => load_flags(memory, x)?,
+            Instruction::SetPitch { x } => {
+                let vx = memory.get_var_register(x)?;
+                memory.set_pitch(vx)
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fetch(memory: &mut Memory) -> Result<u16, Fault> {
+    let ins_mem = memory.fetch_instruction()?;
+    memory.increment_pc();
+    Ok((ins_mem.0 as u16) << 8 | (ins_mem.1 as u16))
+}
+
+fn reset_vf(memory: &mut Memory, quirks: Quirks) -> Result<(), Fault> {
+    if quirks.vf_reset {
+        memory.set_var_register(0xF, 0)?;
+    }
+    Ok(())
+}
+
+fn var_registers(memory: &Memory, x: u8, y: u8) -> Result<(u8, u8), Fault> {
+    Ok((memory.get_var_register(x)?, memory.get_var_register(y)?))
+}
+
+fn load_registers(memory: &mut Memory, x: u8, increment_index: bool) -> Result<(), Fault> {
+    let index = memory.index_register();
+    let mut register;
+    for i in 0..=x {
+        register = memory.read_ram(index as usize + i as usize)?;
+        memory.set_var_register(i, register)?;
+    }
+    if increment_index {
+        memory.set_index_register(index.wrapping_add(x as u16 + 1));
+    }
+    Ok(())
+}
+
+fn store_registers(memory: &mut Memory, x: u8, increment_index: bool) -> Result<(), Fault> {
+    let index = memory.index_register();
+    let mut register_buffer = Vec::with_capacity(x as usize + 1);
+    for i in 0..=x {
+        register_buffer.push(memory.get_var_register(i)?);
+    }
+    memory.write_ram(index, &register_buffer)?;
+    if increment_index {
+        memory.set_index_register(index.wrapping_add(x as u16 + 1));
+    }
+    Ok(())
+}
+
+fn to_digits(memory: &mut Memory, mut vx: u8) -> Result<(), Fault> {
+    let index_register = memory.index_register();
+    let mut digits: [u8; 3] = [0; 3];
+    for digit in digits.iter_mut().rev() {
+        *digit = vx % 10;
+        vx /= 10;
+    }
+    memory.write_ram(index_register, &digits)
+}
+
+fn get_font_char(memory: &mut Memory, vx: u8) {
+    let char = vx & 0x0F;
+    memory.set_index_register(FONT_ADDRESS + 5 * char as u16)
+}
+
+fn get_big_font_char(memory: &mut Memory, vx: u8) {
+    let char = vx & 0x0F;
+    memory.set_index_register(BIG_FONT_ADDRESS + 10 * char as u16)
+}
+
+fn store_flags(memory: &mut Memory, x: u8) -> Result<(), Fault> {
+    for i in 0..=x {
+        let register = memory.get_var_register(i)?;
+        memory.set_flag_register(i, register)?;
+    }
+    Ok(())
+}
+
+// 5XY2 / 5XY3: the range may be descending (x > y), I is left unchanged
+fn register_range(x: u8, y: u8) -> Vec<u8> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+fn save_range(memory: &mut Memory, x: u8, y: u8) -> Result<(), Fault> {
+    let index = memory.index_register();
+    let mut register_buffer = Vec::with_capacity(16);
+    for i in register_range(x, y) {
+        register_buffer.push(memory.get_var_register(i)?);
+    }
+    memory.write_ram(index, &register_buffer)
+}
+
+fn load_range(memory: &mut Memory, x: u8, y: u8) -> Result<(), Fault> {
+    let index = memory.index_register();
+    for (offset, i) in register_range(x, y).into_iter().enumerate() {
+        let register = memory.read_ram(index as usize + offset)?;
+        memory.set_var_register(i, register)?;
+    }
+    Ok(())
+}
+
+fn load_audio_pattern(memory: &mut Memory) -> Result<(), Fault> {
+    let index = memory.index_register();
+    let mut pattern = [0u8; 16];
+    for (offset, sample) in pattern.iter_mut().enumerate() {
+        *sample = memory.read_ram(index as usize + offset)?;
+    }
+    memory.set_audio_pattern(pattern);
+    Ok(())
+}
+
+fn load_flags(memory: &mut Memory, x: u8) -> Result<(), Fault> {
+    for i in 0..=x {
+        let register = memory.get_flag_register(i)?;
+        memory.set_var_register(i, register)?;
+    }
+    Ok(())
+}
+
+fn wait_for_keyinput(memory: &mut Memory, key_flags: u16, x: u8) -> Result<(), Fault> {
+    if key_flags > 0 {
+        memory.set_var_register(x, key_flags.trailing_zeros() as u8)?
+    } else {
+        memory.decrement_pc();
+    }
+    Ok(())
+}
+
+// I wraps around at 16 bits, the XO-CHIP address space
+fn add_to_index(memory: &mut Memory, vx: u8, overflow_flag: bool) -> Result<(), Fault> {
+    let res = memory.index_register() as usize + vx as usize;
+    if overflow_flag && res > 0x0FFF {
+        memory.set_var_register(0xF, 1)?;
+    }
+    memory.set_index_register(res as u16);
+    Ok(())
+}
+
+fn skip_if_key(x: u8, pressed: bool, memory: &mut Memory, key_flags: u16) -> Result<(), Fault> {
+    let key = memory.get_var_register(x)?;
+    if key > 0xF {
+        return Err(Fault::InvalidKey { key });
+    }
+    let is_pressed = key_flags & (1u16 << key) > 0;
+    if is_pressed == pressed {
+        skip_instruction(memory)
+    }
+    Ok(())
+}
+
+fn jump_with_offset(memory: &mut Memory, x: u8, nnn: u16, jump_uses_vx: bool) -> Result<(), Fault> {
+    if jump_uses_vx {
+        let vx = memory.get_var_register(x)?;
+        memory.jump_pc(nnn + vx as u16);
+    } else {
+        let v0 = memory.get_var_register(0)?;
+        memory.jump_pc(nnn + v0 as u16);
+    }
+    Ok(())
+}
+
+fn shift_right(
+    memory: &mut Memory,
+    x: u8,
+    vx: u8,
+    vy: u8,
+    shift_uses_vy: bool,
+) -> Result<(), Fault> {
+    let src = if shift_uses_vy { vy } else { vx };
+    memory.set_var_register(x, src >> 1)?;
+    memory.set_var_register(0xF, 0b00000001u8 & src)
+}
+
+fn shift_left(
+    memory: &mut Memory,
+    x: u8,
+    vx: u8,
+    vy: u8,
+    shift_uses_vy: bool,
+) -> Result<(), Fault> {
+    let src = if shift_uses_vy { vy } else { vx };
+    memory.set_var_register(x, src << 1)?;
+    memory.set_var_register(0xF, src >> 7)
+}
+
+fn sub_x_y(memory: &mut Memory, x: u8, vx: u8, vy: u8) -> Result<(), Fault> {
+    // the flag is written last, so it wins when x is F
+    memory.set_var_register(x, vx.wrapping_sub(vy))?;
+    memory.set_var_register(0xF, (vx >= vy) as u8)
+}
+
+fn sub_y_x(memory: &mut Memory, x: u8, vx: u8, vy: u8) -> Result<(), Fault> {
+    memory.set_var_register(x, vy.wrapping_sub(vx))?;
+    memory.set_var_register(0xF, (vy >= vx) as u8)
+}
+
+fn add(memory: &mut Memory, x: u8, vx: u8, vy: u8) -> Result<(), Fault> {
+    let (res, carry) = vx.overflowing_add(vy);
+    memory.set_var_register(x, res)?;
+    memory.set_var_register(0xF, carry as u8)
+}
+
+// skips the next instruction, the XO-CHIP F000 NNNN instruction is 4 bytes long
+fn skip_instruction(memory: &mut Memory) {
+    if memory.fetch_instruction() == Ok((0xF0, 0x00)) {
+        memory.increment_pc();
+    }
+    memory.increment_pc();
+}
+
+fn skip_if_neq(x: u8, y: u8, memory: &mut Memory) -> Result<(), Fault> {
+    let vx = memory.get_var_register(x)?;
+    let vy = memory.get_var_register(y)?;
+    if vx != vy {
+        skip_instruction(memory);
+    }
+    Ok(())
+}
+
+fn skip_if_eq(x: u8, y: u8, memory: &mut Memory) -> Result<(), Fault> {
+    let vx = memory.get_var_register(x)?;
+    let vy = memory.get_var_register(y)?;
+    if vx == vy {
+        skip_instruction(memory);
+    }
+    Ok(())
+}
+
+fn skip_if_neq_im(x: u8, nn: u8, memory: &mut Memory) -> Result<(), Fault> {
+    let vx = memory.get_var_register(x)?;
+    if vx != nn {
+        skip_instruction(memory);
+    }
+    Ok(())
+}
+
+fn skip_if_eq_im(x: u8, nn: u8, memory: &mut Memory) -> Result<(), Fault> {
+    let vx = memory.get_var_register(x)?;
+    if vx == nn {
+        skip_instruction(memory);
+    }
+    Ok(())
+}
+
+fn add_var_register(memory: &mut Memory, x: u8, nn: u8) -> Result<(), Fault> {
+    let curr_var = memory.get_var_register(x)?;
+    let result = (nn as usize) + (curr_var as usize);
+    memory.set_var_register(x, result as u8)?;
+    Ok(())
+}
+
+// DXYN: draws an 8xN sprite, DXY0 a 16x16 sprite (2 bytes per row) if wide_sprites is set
+// with several planes selected, the sprite data of each plane follows the previous one
+fn draw_sprite(
+    x: u8,
+    y: u8,
+    n: u8,
+    wide_sprites: bool,
+    clip_sprites: bool,
+    memory: &mut Memory,
+    framebuffer: &mut Framebuffer,
+) -> Result<(), Fault> {
+    let mut index_register = memory.index_register() as usize;
+    let (width, height) = (framebuffer.width(), framebuffer.height());
+    let x_off = memory.get_var_register(x)? as usize % width;
+    let y_off = memory.get_var_register(y)? as usize % height;
+    let (sprite_width, sprite_height) = if n == 0 && wide_sprites {
+        (16usize, 16usize)
+    } else {
+        (8usize, n as usize)
+    };
+    let bytes_per_row = sprite_width / 8;
+    let mut x_cord;
+    let mut y_cord;
+    let mut sprite_byte;
+    let mut vf = 0u8;
+    let mut new_pixel;
+    let mut curr_pixel;
+
+    for plane in framebuffer.selected().collect::<Vec<_>>() {
+        for row in 0..sprite_height {
+            y_cord = y_off + row;
+            if y_cord >= height {
+                if clip_sprites {
+                    break;
+                }
+                y_cord %= height;
+            }
+
+            for col in 0..sprite_width {
+                x_cord = x_off + col;
+                if x_cord >= width {
+                    if clip_sprites {
+                        break;
+                    }
+                    x_cord %= width;
+                }
+                let address = index_register + row * bytes_per_row + col / 8;
+                sprite_byte = memory.read_ram(address)?;
+                new_pixel = (sprite_byte & (128u8 >> (col % 8))) != 0;
+                if new_pixel {
+                    curr_pixel = framebuffer.get_pixel(plane, x_cord as u8, y_cord as u8)?;
+                    if curr_pixel {
+                        vf = 1
+                    }
+                    framebuffer.set_pixel(
+                        plane,
+                        x_cord as u8,
+                        y_cord as u8,
+                        curr_pixel ^ new_pixel,
+                    );
+                }
+            }
+        }
+        index_register += sprite_height * bytes_per_row;
+    }
+    memory.set_var_register(0xF, vf)?;
+    framebuffer.mark_dirty();
+    Ok(())
+}
+
+fn call_subroutine(nnn: u16, memory: &mut Memory) -> Result<(), Fault> {
+    let pc = memory.pc();
+    memory.push_stack(pc)?;
+    memory.jump_pc(nnn);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // machine with `rom` loaded that has executed `steps` instructions
+    fn run(mode: Mode, quirks: Quirks, rom: &[u8], steps: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(mode, quirks);
+        chip8.load_rom_bytes(rom).unwrap();
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    fn v(chip8: &Chip8, x: usize) -> u8 {
+        chip8.memory().var_registers()[x]
+    }
+
+    #[test]
+    fn shifts_use_vy_only_with_the_quirk() {
+        // v0 := 0x81, v1 := 0x42, then 8016 (v0 >>= v1) or 801E (v0 <<= v1)
+        let rom = |op: u8| [0x60, 0x81, 0x61, 0x42, 0x80, op];
+        let shifted = |op: u8, shift_uses_vy: bool| {
+            let quirks = Quirks {
+                shift_uses_vy,
+                ..Quirks::default()
+            };
+            let chip8 = run(Mode::Chip8, quirks, &rom(op), 3);
+            (v(&chip8, 0), v(&chip8, 0xF))
+        };
+        assert_eq!(shifted(0x16, false), (0x40, 1));
+        assert_eq!(shifted(0x16, true), (0x21, 0));
+        assert_eq!(shifted(0x1E, false), (0x02, 1));
+        assert_eq!(shifted(0x1E, true), (0x84, 0));
+    }
+
+    #[test]
+    fn index_overflow_sets_vf_only_outside_xo_chip() {
+        // i := 0xFFF, v0 := 2, vf := 0, i += v0
+        let rom = [0xAF, 0xFF, 0x60, 0x02, 0x6F, 0x00, 0xF0, 0x1E];
+        let quirks = Quirks {
+            index_overflow: true,
+            ..Quirks::default()
+        };
+        let chip8 = run(Mode::Chip8, quirks, &rom, 4);
+        assert_eq!(
+            (chip8.memory().index_register(), v(&chip8, 0xF)),
+            (0x1001, 1)
+        );
+        let chip8 = run(Mode::Chip8, Quirks::default(), &rom, 4);
+        assert_eq!(v(&chip8, 0xF), 0);
+        let chip8 = run(Mode::XoChip, quirks, &rom, 4);
+        assert_eq!(
+            (chip8.memory().index_register(), v(&chip8, 0xF)),
+            (0x1001, 0)
+        );
+        // and wraps at the end of the 64 KiB address space
+        let rom = [0xF0, 0x00, 0xFF, 0xFF, 0x60, 0x02, 0xF0, 0x1E];
+        let chip8 = run(Mode::XoChip, Quirks::for_mode(Mode::XoChip), &rom, 3);
+        assert_eq!(chip8.memory().index_register(), 0x0001);
+    }
+
+    fn pixel(chip8: &Chip8, x: u8, y: u8) -> bool {
+        chip8.framebuffer().get_pixel(0, x, y).unwrap()
+    }
+
+    #[test]
+    fn switching_resolution_clears_and_resizes_the_screen() {
+        // i := hex 0, draw it at (0, 0), hires, lores
+        let rom = [0xA0, 0x50, 0xD0, 0x05, 0x00, 0xFF, 0x00, 0xFE];
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 2);
+        assert!(pixel(&chip8, 0, 0));
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 3);
+        let framebuffer = chip8.framebuffer();
+        assert_eq!((framebuffer.width(), framebuffer.height()), (128, 64));
+        assert!(framebuffer.colors().iter().all(|color| *color == 0));
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 4);
+        assert_eq!(chip8.framebuffer().colors(), vec![0; 64 * 32]);
+    }
+
+    #[test]
+    fn big_sprites_are_16x16_and_collide() {
+        // hires, i := 0x20A, draw the 16x16 sprite at (0, 0) twice
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x0A, 0xD0, 0x00, 0xD0, 0x00, 0x12, 0x08];
+        rom.extend([0xFF; 32]);
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 3);
+        assert!(pixel(&chip8, 0, 0) && pixel(&chip8, 15, 15));
+        assert!(!pixel(&chip8, 16, 0) && !pixel(&chip8, 0, 16));
+        assert_eq!(v(&chip8, 0xF), 0);
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 4);
+        assert!(chip8.framebuffer().colors().iter().all(|color| *color == 0));
+        assert_eq!(v(&chip8, 0xF), 1);
+        // CHIP-8 draws nothing for DXY0
+        let chip8 = run(Mode::Chip8, Quirks::default(), &rom[2..], 2);
+        assert!(!pixel(&chip8, 0, 0));
+    }
+
+    #[test]
+    fn scrolling_drops_pixels_at_the_screen_edges() {
+        let rom = [
+            0x00, 0xFF, // hires
+            0x60, 0x7E, // v0 := 126
+            0x61, 0x00, // v1 := 0
+            0xA2, 0x1A, // i := 0x21A
+            0xD0, 0x11, // two pixels at (126, 0)
+            0x00, 0xFC, // scroll left
+            0x00, 0xFB, // scroll right
+            0x00, 0xFB, // scroll right
+            0x61, 0x3F, // v1 := 63
+            0xD0, 0x11, // two pixels at (126, 63)
+            0x00, 0xC1, // scroll down 1
+            0x12, 0x16, // loop
+            0x00, 0x00, 0xC0,
+        ];
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 6);
+        assert!(pixel(&chip8, 122, 0) && pixel(&chip8, 123, 0));
+        assert!(!pixel(&chip8, 126, 0) && !pixel(&chip8, 127, 0));
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 7);
+        assert!(pixel(&chip8, 126, 0) && pixel(&chip8, 127, 0));
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 8);
+        assert!(chip8.framebuffer().colors().iter().all(|color| *color == 0));
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 10);
+        assert!(pixel(&chip8, 126, 63));
+        let chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 11);
+        assert!(chip8.framebuffer().colors().iter().all(|color| *color == 0));
+    }
+
+    #[test]
+    fn big_font_chars_are_10_bytes_apart() {
+        // v0 := 9, i := big hex v0
+        let chip8 = run(
+            Mode::SuperChip,
+            Quirks::default(),
+            &[0x60, 0x09, 0xF0, 0x30],
+            2,
+        );
+        let index = chip8.memory().index_register();
+        assert_eq!(index, BIG_FONT_ADDRESS + 90);
+        let char: Vec<u8> = (index..index + 10)
+            .map(|address| chip8.memory().read_ram_cell(address).unwrap())
+            .collect();
+        assert_eq!(
+            char,
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn flags_persist_registers_and_exit_stops_the_machine() {
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, // v0 := 1, v1 := 2, v2 := 3
+            0xF2, 0x75, // saveflags v2
+            0x60, 0x00, 0x61, 0x00, 0x62, 0x00, // v0 := 0, v1 := 0, v2 := 0
+            0xF1, 0x85, // loadflags v1
+            0x00, 0xFD, // exit
+        ];
+        let mut chip8 = run(Mode::SuperChip, Quirks::default(), &rom, 9);
+        assert_eq!((v(&chip8, 0), v(&chip8, 1), v(&chip8, 2)), (1, 2, 0));
+        assert!(chip8.exited());
+        let pc = chip8.memory().pc();
+        chip8.step().unwrap();
+        assert_eq!(chip8.memory().pc(), pc);
+    }
+
+    #[test]
+    fn register_ranges_may_be_descending() {
+        let rom = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, // v1 := 1, v2 := 2, v3 := 3
+            0xA3, 0x00, // i := 0x300
+            0x53, 0x12, // save v3 - v1
+            0x51, 0x33, // load v1 - v3
+        ];
+        let chip8 = run(Mode::XoChip, Quirks::for_mode(Mode::XoChip), &rom, 5);
+        let saved: Vec<u8> = (0x300..0x303)
+            .map(|address| chip8.memory().read_ram_cell(address).unwrap())
+            .collect();
+        assert_eq!(saved, [3, 2, 1]);
+        assert_eq!(chip8.memory().index_register(), 0x300);
+        let chip8 = run(Mode::XoChip, Quirks::for_mode(Mode::XoChip), &rom, 6);
+        assert_eq!((v(&chip8, 1), v(&chip8, 2), v(&chip8, 3)), (3, 2, 1));
+    }
+
+    #[test]
+    fn skips_step_over_long_index_loads() {
+        // v0 := 0, skip if v0 == 0, i := long 0x1234, v1 := 1
+        let rom = [0x60, 0x00, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01];
+        let chip8 = run(Mode::XoChip, Quirks::for_mode(Mode::XoChip), &rom, 2);
+        assert_eq!(chip8.memory().pc(), 0x208);
+        // v0 := 1, the skip isn't taken and i := long 0x1234 is executed
+        let rom = [0x60, 0x01, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01];
+        let chip8 = run(Mode::XoChip, Quirks::for_mode(Mode::XoChip), &rom, 3);
+        assert_eq!(chip8.memory().index_register(), 0x1234);
+        assert_eq!(chip8.memory().pc(), 0x208);
+    }
+
+    #[test]
+    fn planes_are_drawn_and_cleared_separately() {
+        let rom = [
+            0xF2, 0x01, // plane 2
+            0xA2, 0x12, // i := 0x212
+            0xD0, 0x11, // draw at (0, 0)
+            0xF3, 0x01, // plane 3
+            0x60, 0x08, // v0 := 8
+            0xD0, 0x11, // draw at (8, 0), one row for each plane
+            0xF1, 0x01, // plane 1
+            0x00, 0xE0, // clear
+            0x12, 0x10, // loop
+            0x80, 0xC0,
+        ];
+        let quirks = Quirks::for_mode(Mode::XoChip);
+        let chip8 = run(Mode::XoChip, quirks, &rom, 3);
+        assert_eq!(chip8.framebuffer().color(0, 0), 2);
+        assert!(chip8.framebuffer().plane(0).not_any());
+        let chip8 = run(Mode::XoChip, quirks, &rom, 6);
+        let framebuffer = chip8.framebuffer();
+        assert_eq!(framebuffer.selected_planes(), 3);
+        assert_eq!((framebuffer.color(8, 0), framebuffer.color(9, 0)), (3, 2));
+        let chip8 = run(Mode::XoChip, quirks, &rom, 8);
+        let framebuffer = chip8.framebuffer();
+        assert_eq!(
+            (
+                framebuffer.color(0, 0),
+                framebuffer.color(8, 0),
+                framebuffer.color(9, 0)
+            ),
+            (2, 2, 2)
+        );
+    }
+
+    #[test]
+    fn vip_frames_carry_over_the_cycles_they_ran_over() {
+        // v0 := 0 takes 46 cycles, 57 of them pass the 2598 cycles of a frame by 24
+        let rom = [0x60, 0x00].repeat(200);
+        let mut chip8 = run(Mode::Chip8, Quirks::default(), &rom, 0);
+        chip8.run_vip_frame().unwrap();
+        assert_eq!((chip8.instruction_count(), chip8.frame_progress), (57, 24));
+        chip8.run_vip_frame().unwrap();
+        assert_eq!((chip8.instruction_count(), chip8.frame_progress), (113, 2));
+        // a fixed number of instructions carries nothing over
+        let mut chip8 = run(Mode::Chip8, Quirks::default(), &rom, 0);
+        chip8.run_frame(10).unwrap();
+        assert_eq!((chip8.instruction_count(), chip8.frame_progress), (10, 0));
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame() {
+        // v0 := 0, draw, v0 := 0
+        let rom = [0x60, 0x00, 0xD0, 0x01, 0x60, 0x00];
+        let quirks = Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        };
+        for scheduler in [Scheduler::Instructions(10), Scheduler::VipCycles] {
+            let mut chip8 = run(Mode::Chip8, quirks, &rom, 0);
+            chip8.memory_mut().set_delay_register(2);
+            assert!(!chip8.step_in_frame(scheduler).unwrap());
+            assert!(chip8.step_in_frame(scheduler).unwrap());
+            assert_eq!(chip8.instruction_count(), 2);
+            assert_eq!(chip8.memory().delay_register(), 1);
+            assert_eq!(chip8.frame_progress, 0);
+            assert!(!chip8.waiting());
+            assert!(!chip8.step_in_frame(scheduler).unwrap());
+            assert_eq!(chip8.instruction_count(), 3);
+        }
+    }
+}
@@ -1,423 +1,680 @@
-use clap::{arg, command, value_parser, ArgAction};
+use chip8::assembler::assemble;
+use chip8::audio::{AudioRecorder, Synth, Tone, SAMPLE_RATE, WAVEFORMS};
+use chip8::config::{ConfigFile, Hotkeys, Settings, WindowSize};
+use chip8::database::{sha1, Database, RomInfo};
+use chip8::debugger::{Breakpoint, Debugger, Watchpoint};
+use chip8::disassembler::disassemble;
+use chip8::image::Screenshots;
+#[cfg(any(feature = "sfml", feature = "tui"))]
+use chip8::keymap::Keymap;
+use chip8::movie::Movie;
+use chip8::palette::Palette;
+use chip8::quirks::{Quirks, PRESETS};
+use chip8::rewind::Rewind;
+use chip8::timing::Scheduler;
+use chip8::trace::Tracer;
+use chip8::video::VideoRecorder;
+use chip8::{Chip8, Chip8Error, Mode};
+use clap::{arg, command, value_parser, Arg, ArgAction, ArgMatches, Command};
+#[cfg(feature = "sfml")]
 use display::Screen;
-use memory::Memory;
-use rand::random;
-use sfml::system::{sleep, Clock, Time};
-use std::fs;
-
+use emulation::Emulation;
+#[cfg(any(feature = "sfml", feature = "tui"))]
+use session::Frontend;
+#[cfg(feature = "sfml")]
+use sfml::audio::SoundStreamPlayer;
+#[cfg(feature = "sfml")]
+use sound::BeeperStream;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+#[cfg(all(feature = "tui", not(feature = "sfml")))]
+use terminal::Bell;
+#[cfg(feature = "tui")]
+use terminal::Terminal;
+
+#[cfg(any(feature = "sfml", feature = "tui"))]
+mod debug_console;
+#[cfg(feature = "sfml")]
 mod display;
-mod memory;
+mod emulation;
+#[cfg(any(feature = "sfml", feature = "tui"))]
+mod session;
+#[cfg(feature = "sfml")]
+mod sound;
+#[cfg(feature = "tui")]
+mod terminal;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
 
-fn main() -> Result<(), String> {
+fn run() -> Result<(), Chip8Error> {
     let matches = command!()
-        .arg(arg!(path: [path] "Path of rom file").required(true))
-        .arg(
-            arg!(-l --legacy ... "Run with old instructions on")
-                .required(false)
-                .action(ArgAction::SetTrue),
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .args(run_args())
+        .subcommand(
+            Command::new("run")
+                .about("Run a rom, same as leaving out the subcommand")
+                .args(run_args()),
         )
-        .arg(
-            arg!(-f --frequency [FREQUENCY] ... "Run with specified frequency")
-                .required(false)
-                .value_parser(value_parser!(f32))
-                .default_value("700"),
+        .subcommand(
+            Command::new("asm")
+                .about("Assemble Octo source into a rom")
+                .arg(arg!(path: <path> "Path of Octo source file"))
+                .arg(
+                    arg!(-o --output [FILE] "Path of the rom, defaults to the source path with a .ch8 extension")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("disasm")
+                .about("Disassemble a rom")
+                .arg(arg!(path: <path> "Path of rom file"))
+                .arg(
+                    arg!(-s --syntax [SYNTAX] "Mnemonics to print")
+                        .required(false)
+                        .value_parser(["octo", "cowgod"])
+                        .default_value("octo"),
+                )
+                .arg(
+                    arg!(-m --mode [MODE] "Instruction set of the rom")
+                        .required(false)
+                        .value_parser(["chip8", "schip", "xochip"])
+                        .default_value("chip8"),
+                )
+                .arg(
+                    arg!(-q --quirks [PRESET] "Quirk preset, with the jump quirk BNNN is JP VX in cowgod syntax, defaults to the one matching the mode")
+                        .required(false)
+                        .value_parser(PRESETS),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Inspect the configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("dump")
+                        .about("Print the configuration a run with these flags would use")
+                        .args(run_args().into_iter().map(|arg| {
+                            if arg.get_id() == "path" {
+                                arg.required(false)
+                            } else {
+                                arg
+                            }
+                        })),
+                ),
         )
         .get_matches();
-    let mut screen = Screen::new((800u32, 400u32), "chip8");
-    let mut memory = Memory::new();
-    let mut cycle_clock = Clock::start();
-    let old_instructions = matches.get_flag("legacy");
-    let frequency = matches.get_one::<f32>("frequency").unwrap();
-
-    load_rom(&mut memory, matches.get_one::<String>("path").unwrap())?;
-
-    loop {
-        screen.handle_events();
-        if screen.closed() {
-            break;
-        }
-        emulate_cycle(&mut memory, &mut screen, old_instructions)?;
-        update_timers(&mut cycle_clock, &mut memory);
-        sleep(Time::seconds(1f32 / frequency));
+    match matches.subcommand() {
+        Some(("run", matches)) => run_rom(matches),
+        Some(("config", matches)) => dump_config(matches.subcommand_matches("dump").unwrap()),
+        Some(("asm", matches)) => assemble_source(matches),
+        Some(("disasm", matches)) => disassemble_rom(matches),
+        _ => run_rom(&matches),
     }
-    Ok(())
 }
 
-fn emulate_cycle(
-    memory: &mut Memory,
-    screen: &mut Screen,
-    old_instructions: bool,
-) -> Result<(), String> {
-    let op_code = fetch(memory);
-    decode_and_execute(op_code, screen, memory, old_instructions)
+fn run_args() -> Vec<Arg> {
+    vec![
+        arg!(path: [path] "Path of rom file").required(true),
+        arg!(--source "Path is Octo source, assemble it and run the result")
+            .required(false)
+            .action(ArgAction::SetTrue),
+        arg!(-l --legacy ... "Run with old instructions on (same as --quirks cosmac-vip)")
+            .required(false)
+            .action(ArgAction::SetTrue),
+        arg!(-q --quirks [PRESET] "Quirk preset, defaults to the one matching the mode")
+            .required(false)
+            .value_parser(PRESETS),
+        arg!(--"shift-quirk" [BOOL] "8XY6/8XYE shift var[y] into var[x]")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(--"memory-quirk" [BOOL] "FX55/FX65 increment I")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(--"jump-quirk" [BOOL] "BXNN jumps to XNN + var[x] instead of NNN + var[0]")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(--"vf-reset" [BOOL] "8XY1/8XY2/8XY3 reset var[F]")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(--"clip-sprites" [BOOL] "Clip sprites at the screen edges instead of wrapping")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(--"display-wait" [BOOL] "DXYN waits for the next frame")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(--"index-overflow" [BOOL] "FX1E sets var[F] when I passes 0xFFF, never in xochip mode")
+            .required(false)
+            .value_parser(value_parser!(bool)),
+        arg!(-m --mode [MODE] "Instruction set to run, defaults to chip8")
+            .required(false)
+            .value_parser(["chip8", "schip", "xochip"]),
+        arg!(-i --ipf [IPF] "Instructions executed per 60Hz frame, defaults to 12")
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..)),
+        // replaced by --ipf, kept so old command lines still work
+        arg!(-f --frequency [FREQUENCY] "Instructions per second, deprecated: use --ipf")
+            .required(false)
+            .hide(true)
+            .conflicts_with("ipf")
+            .value_parser(value_parser!(f32)),
+        arg!(--"vip-timing" "Give every instruction its COSMAC VIP cycle cost instead of using --ipf")
+            .required(false)
+            .conflicts_with_all(["ipf", "frequency"])
+            .action(ArgAction::SetTrue),
+        arg!(--palette [COLORS] "Colors of the background and the XO-CHIP planes: #RRGGBB,#RRGGBB,...")
+            .required(false)
+            .value_parser(|colors: &str| colors.parse::<Palette>()),
+        arg!(--"window-size" [SIZE] "Size of the window: WIDTHxHEIGHT, defaults to 800x400")
+            .required(false)
+            .value_parser(|size: &str| size.parse::<WindowSize>()),
+        arg!(--scale [N] "Size of a CHIP-8 pixel in screenshots and recordings, defaults to 8")
+            .required(false)
+            .value_parser(value_parser!(u32).range(1..)),
+        arg!(--waveform [WAVEFORM] "Waveform of the beeper, defaults to square")
+            .required(false)
+            .value_parser(WAVEFORMS),
+        arg!(--tone [HZ] "Frequency of the beeper, defaults to 440")
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        arg!(--volume [VOLUME] "Volume of the beeper from 0.0 to 1.0, defaults to 0.25")
+            .required(false)
+            .value_parser(value_parser!(f32)),
+        arg!(--mute "Don't play the beeper")
+            .required(false)
+            .action(ArgAction::SetTrue),
+        arg!(--headless "Run without a window or sound, requires --frames or --replay")
+            .required(false)
+            .action(ArgAction::SetTrue),
+        arg!(--frames [N] "Stop after N frames")
+            .required(false)
+            .value_parser(value_parser!(u64)),
+        arg!(--"screenshot-every" [K] "Save every K-th frame as a PNG image, requires --out")
+            .required(false)
+            .requires("out")
+            .value_parser(value_parser!(u64).range(1..)),
+        arg!(--out [DIR] "Directory the screenshots are saved to")
+            .required(false),
+        arg!(--wav [FILE] "Write the sound of the whole run to a wav file")
+            .required(false),
+        arg!(--record [FILE] "Record every frame to an animated GIF (.gif) or a Y4M stream (.y4m, - for stdout)")
+            .required(false),
+        arg!(--tui "Run in the terminal instead of a window (needs the tui feature)")
+            .required(false)
+            .conflicts_with("headless")
+            .action(ArgAction::SetTrue),
+        arg!(-d --debug "Start paused in the debugger, commands are read from stdin")
+            .required(false)
+            .conflicts_with_all(["headless", "tui"])
+            .action(ArgAction::SetTrue),
+        arg!(-b --break [BREAKPOINT] "Debugger breakpoint, an address (0x2A0) or an opcode pattern (DXYN)")
+            .required(false)
+            .requires("debug")
+            .value_parser(|breakpoint: &str| breakpoint.parse::<Breakpoint>())
+            .action(ArgAction::Append),
+        arg!(-w --watch [WATCHPOINT] "Debugger watchpoint: \"ram ADDR[-ADDR] [r|w|rw]\", vX[=VALUE] or \"i ADDR[-ADDR]\"")
+            .required(false)
+            .requires("debug")
+            .value_parser(|watchpoint: &str| watchpoint.parse::<Watchpoint>())
+            .action(ArgAction::Append),
+        arg!(--trace [FILE] "Log every executed instruction with the machine state to a file")
+            .required(false),
+        arg!(--"trace-format" [FORMAT] "Format of the trace, json writes one object per line")
+            .required(false)
+            .value_parser(["text", "json"])
+            .default_value("text"),
+        arg!(--keymap [KEYMAP] "Keyboard layout (qwerty, qwertz, azerty, numpad) or a keymap TOML file, defaults to qwertz")
+            .required(false),
+        arg!(--rewind [SECONDS] "Seconds of gameplay kept to rewind with backspace, 0 turns rewinding off, defaults to 30")
+            .required(false)
+            .value_parser(value_parser!(u32)),
+        arg!(--"load-state" [FILE] "Start from a save state instead of the beginning of the rom")
+            .required(false),
+        arg!(--seed [SEED] "Seed of the CXNN random numbers, random by default")
+            .required(false)
+            .value_parser(value_parser!(u32)),
+        arg!(--"record-movie" [FILE] "Record the keypad input of every frame to a movie file")
+            .required(false)
+            .conflicts_with_all(["load-state", "replay"]),
+        arg!(--replay [MOVIE] "Replay a movie recorded with --record-movie, the keypad takes over when it ends")
+            .required(false)
+            .conflicts_with_all(["load-state", "seed"]),
+        arg!(--"no-detect" "Don't pick mode, quirks, speed and colors from the rom database")
+            .required(false)
+            .action(ArgAction::SetTrue),
+        arg!(--database [DIR] "Directory with a copy of the CHIP-8 rom database")
+            .required(false),
+        arg!(--config [FILE] "Config file, defaults to chip8/config.toml in the user's config directory")
+            .required(false),
+    ]
 }
 
-fn update_timers(cycle_clock: &mut Clock, memory: &mut Memory) {
-    if cycle_clock.elapsed_time().as_seconds() > 1f32 / 60f32 {
-        memory.decrement_delay();
-        memory.decrement_sound();
+fn run_rom(matches: &ArgMatches) -> Result<(), Chip8Error> {
+    let path = Path::new(matches.get_one::<String>("path").unwrap());
+    let rom = read_rom(matches, path)?;
+    let rom_info = if matches.get_flag("no-detect") {
+        None
     } else {
-        cycle_clock.restart();
+        detect_rom(matches, &rom)?
+    };
+    let record = matches.get_one::<String>("record").map(Path::new);
+    if let Some(info) = &rom_info {
+        // a video streamed to stdout must not be mixed with text
+        if record == Some(Path::new("-")) {
+            eprint!("{}", describe_rom(info));
+        } else {
+            print!("{}", describe_rom(info));
+        }
     }
-}
-
-fn fetch(memory: &mut Memory) -> u16 {
-    let ins_mem = memory.fetch_instruction();
-    memory.increment_pc();
-    (ins_mem.0 as u16) << 8 | (ins_mem.1 as u16)
-}
 
-fn decode_and_execute(
-    op_code: u16,
-    screen: &mut Screen,
-    memory: &mut Memory,
-    old_instructions: bool,
-) -> Result<(), String> {
-    let x = ((0x0F00u16 & op_code) >> 8) as u8;
-    let y = ((0x00F0u16 & op_code) >> 4) as u8;
-    let n = (0x000Fu16 & op_code) as u8;
-    let nn = (0x00FFu16 & op_code) as u8;
-    let nnn = 0x0FFFu16 & op_code;
-
-    let res = match op_code & 0xF000 {
-        0x0000u16 => zero_instructions(op_code, screen, memory),
-        0x1000u16 => Ok(memory.jump_pc(nnn)), // 1NNN: jump
-        0x2000u16 => Ok(call_subroutine(nnn, memory)), // 2NNN: call subroutine
-        0x3000u16 => skip_if_eq_im(x, nn, memory), // 3XNN: skip if var[x] == nn
-        0x4000u16 => skip_if_neq_im(x, nn, memory), // 4XNN: skip if var[x] != nn
-        0x5000u16 => skip_if_eq(x, y, memory), // 5XY0: skip if var[x] == var[y]
-        0x6000u16 => memory.set_var_register(x, nn), //6XNN: var[x] := nn
-        0x7000u16 => add_var_register(memory, x, nn), // 7XNN: var[x] := var[x] + nn
-        0x8000u16 => basic_operations(op_code, memory, old_instructions),
-        0x9000u16 => skip_if_neq(x, y, memory), // 9XY0: skip if var[x] != var[y]
-        0xA000u16 => Ok(memory.set_index_register(nnn)), // ANNN: I := nnn
-        0xB000u16 => jump_with_offset(memory, x, nnn, old_instructions), // BXNN: jump with offset
-        0xC000u16 => memory.set_var_register(x, random::<u8>() & nn), // CXNN: V[x] := rand & nn
-        0xD000u16 => draw_sprite(x, y, n, memory, screen), // DXYN: Display (Draw)
-        0xE000u16 => skip_if_key(op_code, x, memory, screen),
-        0xF000u16 => f_instructions(op_code, x, memory, screen, old_instructions),
-        _ => Err("Invalid op code".to_string()),
-    };
-    if let Err(err) = res {
-        return Err(format!(
-            "Error in instrcution with opcode {:#06x}: \n {}",
-            op_code, err
-        ));
+    let replay = replay_movie(matches)?;
+    if let Some(replay) = &replay {
+        replay.check_rom(&rom)?;
+    }
+    let frame_limit = matches.get_one::<u64>("frames").copied();
+    let frame_limit = frame_limit.or(replay.as_ref().map(|replay| replay.frames));
+    if matches.get_flag("headless") && frame_limit.is_none() {
+        return Err(Chip8Error::Config {
+            message: "--headless runs until --frames or the end of --replay".to_string(),
+        });
     }
-    res
-}
 
-fn f_instructions(
-    op_code: u16,
-    x: u8,
-    memory: &mut Memory,
-    screen: &mut Screen,
-    old_instructions: bool,
-) -> Result<(), String> {
-    let vx = memory.get_var_register(x)?;
-    let delay_timer = memory.delay_register();
-    match op_code & 0x00FF {
-        0x0007 => memory.set_var_register(x, delay_timer)?, // FX07: var[x] := delay_timer
-        0x0015 => memory.set_delay_register(vx),            // FX15: delay_timer := var[x]
-        0x0018 => memory.set_sounds_register(vx),           // FX18: sound_timer := var[x]
-        0x001E => add_to_index(memory, vx)?,                // FX1E: I := I + var[x]
-        0x000A => wait_for_keyinput(memory, screen, x)?,    // FX0A: get key input
-        0x0029 => get_font_char(memory, vx), // FX29: I := Font offset of font char var[x]
-        0x0033 => to_digits(memory, vx),     // FX33: 623 -> 6, 2, 3
-        0x0055 => store_registers(memory, x, old_instructions)?, // FX55: store registers in ram
-        0x0065 => load_registers(memory, x, old_instructions)?, // FX65: load registers from ram
-        _ => return Err("Invalid op code".to_string()),
+    let settings = merge_layers(&settings_layers(
+        matches,
+        Some((path, &rom)),
+        rom_info.as_ref(),
+        replay.as_ref(),
+    )?);
+    let mode = settings.mode.unwrap();
+    let quirks = settings.quirks();
+    let scheduler = if settings.vip_timing.unwrap() {
+        Scheduler::VipCycles
+    } else {
+        Scheduler::Instructions(settings.ipf.unwrap())
+    };
+    let tone = Tone {
+        waveform: settings.waveform.unwrap(),
+        frequency: settings.tone.unwrap(),
+        volume: settings.volume.unwrap(),
     };
-    Ok(())
-}
 
-fn load_registers(memory: &mut Memory, x: u8, old_instructions: bool) -> Result<(), String> {
-    let index = memory.index_register();
-    let mut register;
-    for i in 0..=x {
-        register = memory.read_ram_cell(index + i as u16);
-        memory.set_var_register(i, register)?;
+    let mut chip8 = Chip8::new(mode, quirks);
+    chip8.load_rom_bytes(&rom)?;
+    if let Some(state) = matches.get_one::<String>("load-state") {
+        chip8.load_state(&fs::read(state)?)?;
     }
-    if old_instructions {
-        memory.set_index_register(index + x as u16 + 1u16);
+    let movie_path = matches.get_one::<String>("record-movie");
+    // a movie has to know the seed, so recording one picks it instead of the machine
+    let seed = match (&replay, matches.get_one::<u32>("seed")) {
+        (Some(replay), _) => Some(replay.seed),
+        (None, Some(seed)) => Some(*seed),
+        (None, None) => movie_path.map(|_| rand::random()),
+    };
+    if let Some(seed) = seed {
+        chip8.set_seed(seed);
     }
-    Ok(())
-}
-
-fn store_registers(memory: &mut Memory, x: u8, old_instructions: bool) -> Result<(), String> {
-    let index = memory.index_register();
-    let mut register_buffer = Vec::with_capacity(x as usize + 1);
-    for i in 0..=x {
-        register_buffer.push(memory.get_var_register(i)?);
+    let mut emulation = Emulation::new(chip8, scheduler, frame_limit);
+    if let (Some(_), Some(seed)) = (movie_path, seed) {
+        emulation.set_movie(Movie::new(&rom, seed, &settings));
     }
-    memory.write_ram(index, &register_buffer);
-    if old_instructions {
-        memory.set_index_register(index + x as u16 + 1u16);
+    if let Some(replay) = replay {
+        emulation.set_replay(replay);
     }
-    Ok(())
-}
-
-fn to_digits(memory: &mut Memory, mut vx: u8) {
-    let index_register = memory.index_register();
-    let mut digits: [u8; 3] = [0; 3];
-    for digit in digits.iter_mut().rev() {
-        *digit = vx % 10;
-        vx /= 10;
+    let wav = matches.get_one::<String>("wav");
+    if wav.is_some() {
+        emulation.set_recorder(AudioRecorder::new(Synth::new(tone, SAMPLE_RATE)));
     }
-    memory.write_ram(index_register, &digits);
-}
 
-fn get_font_char(memory: &mut Memory, vx: u8) {
-    let char = vx & 0x0F;
-    memory.set_index_register(0x0050 + 5 * char as u16)
-}
-
-fn wait_for_keyinput(memory: &mut Memory, screen: &mut Screen, x: u8) -> Result<(), String> {
-    if screen.any_key_pressed() {
-        memory.set_var_register(x, screen.get_pressed_key())?
-    } else {
-        memory.decrement_pc();
+    if let Some(every) = matches.get_one::<u64>("screenshot-every") {
+        let dir = Path::new(matches.get_one::<String>("out").unwrap());
+        let palette = settings.palette.unwrap();
+        let scale = settings.scale.unwrap();
+        emulation.set_screenshots(Screenshots::new(dir, *every, palette, scale)?);
     }
-    Ok(())
-}
-
-fn add_to_index(memory: &mut Memory, vx: u8) -> Result<(), String> {
-    let res = memory.index_register() as usize + vx as usize;
-    if res > 0x0FFF {
-        memory.set_var_register(0xF, 1)?;
+    if let Some(record) = record {
+        let palette = settings.palette.unwrap();
+        let scale = settings.scale.unwrap();
+        let hires = mode != Mode::Chip8;
+        emulation.set_video(VideoRecorder::create(record, palette, scale, hires)?);
     }
-    memory.set_index_register(res as u16);
-    Ok(())
-}
 
-fn skip_if_key(opcode: u16, x: u8, memory: &mut Memory, screen: &mut Screen) -> Result<(), String> {
-    let is_pressed = screen.key_state(memory.get_var_register(x)?)?;
-    match opcode & 0x00FF {
-        0x009E => {
-            if is_pressed {
-                memory.increment_pc()
-            }
+    if let Some(path) = matches.get_one::<String>("trace") {
+        let format = matches
+            .get_one::<String>("trace-format")
+            .unwrap()
+            .parse()
+            .unwrap();
+        emulation.set_tracer(Tracer::new(BufWriter::new(File::create(path)?), format));
+    }
+    if matches.get_flag("debug") {
+        let mut debugger = Debugger::new();
+        for breakpoint in matches
+            .get_many::<Breakpoint>("break")
+            .into_iter()
+            .flatten()
+        {
+            debugger.add_breakpoint(breakpoint.clone());
         }
-        0x00A1 => {
-            if !is_pressed {
-                memory.increment_pc()
-            }
+        for watchpoint in matches
+            .get_many::<Watchpoint>("watch")
+            .into_iter()
+            .flatten()
+        {
+            debugger.add_watchpoint(&mut emulation.chip8, *watchpoint);
         }
-        _ => return Err("Invalid op code".to_string()),
+        debugger.pause();
+        emulation.debugger = Some(debugger);
     }
-    Ok(())
-}
 
-fn jump_with_offset(
-    memory: &mut Memory,
-    x: u8,
-    nnn: u16,
-    old_instructions: bool,
-) -> Result<(), String> {
-    if old_instructions {
-        let v0 = memory.get_var_register(0).unwrap();
-        memory.jump_pc(nnn + v0 as u16);
+    let rewind_seconds = settings.rewind.unwrap();
+    if rewind_seconds > 0 && !matches.get_flag("headless") {
+        emulation.set_rewind(Rewind::with_seconds(rewind_seconds));
+    }
+
+    let result = if matches.get_flag("headless") {
+        run_headless(&mut emulation)
+    } else if matches.get_flag("tui") {
+        run_terminal(&mut emulation, path, &settings, tone)
     } else {
-        let vx = memory.get_var_register(x)?;
-        memory.jump_pc(nnn + vx as u16);
+        run_window(&mut emulation, path, &settings, tone)
+    };
+    // the trace leading up to a fault is the interesting part
+    emulation.flush_trace()?;
+    if let Some(video) = emulation.take_video() {
+        video.finish()?;
+    }
+    // a movie ending in a fault reproduces the fault
+    if let (Some(path), Some(movie)) = (movie_path, emulation.take_movie()) {
+        fs::write(path, movie.to_toml())?;
+    }
+    result?;
+
+    if let (Some(path), Some(recorder)) = (wav, emulation.recorder()) {
+        recorder.write_wav(BufWriter::new(File::create(path)?))?;
     }
     Ok(())
 }
 
-fn basic_operations(
-    op_code: u16,
-    memory: &mut Memory,
-    old_instructions: bool,
-) -> Result<(), String> {
-    let x = ((0x0F00u16 & op_code) >> 8) as u8;
-    let y = ((0x00F0u16 & op_code) >> 4) as u8;
-    let vx = memory.get_var_register(x)?;
-    let vy = memory.get_var_register(y)?;
+#[cfg(feature = "sfml")]
+fn run_window(
+    emulation: &mut Emulation,
+    rom_path: &Path,
+    settings: &Settings,
+    tone: Tone,
+) -> Result<(), Chip8Error> {
+    let keymap = keymap(rom_path, settings)?;
+    let size = settings.window_size.unwrap();
+    let mut screen = Screen::new(
+        (size.width, size.height),
+        "chip8",
+        settings.palette.unwrap(),
+        keymap,
+        settings.hotkeys.clone(),
+    );
+    run_with_sound(emulation, &mut screen, rom_path, settings, tone)
+}
 
-    match op_code & 0x000Fu16 {
-        0x0000u16 => memory.set_var_register(x, vy), // 8XY0: var[x] := var[y]
-        0x0001u16 => memory.set_var_register(x, vx | vy), // 8XY1: var[x] := var[y] | var[x]
-        0x0002u16 => memory.set_var_register(x, vx & vy), // 8XY2: var[x] := var[y] & var[x]
-        0x0003u16 => memory.set_var_register(x, vx ^ vy), // 8XY3: var[x] := var[x] ^ var[y]
-        0x0004u16 => add(memory, x, vx, vy),         // 8XY4: var[x] := var[x] + var[y]
-        0x0005u16 => sub_x_y(memory, x, vx, vy),     // 8XY5: var[x] := var[x] - var[y]
-        0x0006u16 => shift_right(memory, x, vx, vy, old_instructions), // 8XY6: var[x] := var[x] >> 1
-        0x0007u16 => sub_y_x(memory, x, vx, vy), // 8XY7: var[x] := var[y] - var[x]
-        0x000Eu16 => shift_left(memory, x, vx, vy, old_instructions), // 8XYE: var[x] := var[x] << 1
-        _ => Err("Invalid op code".to_string()),
-    }
+#[cfg(not(feature = "sfml"))]
+fn run_window(_: &mut Emulation, _: &Path, _: &Settings, _: Tone) -> Result<(), Chip8Error> {
+    Err(Chip8Error::Config {
+        message: "built without the window, run with --tui or --headless".to_string(),
+    })
 }
 
-fn shift_right(
-    memory: &mut Memory,
-    x: u8,
-    vx: u8,
-    vy: u8,
-    old_instructions: bool,
-) -> Result<(), String> {
-    if old_instructions {
-        memory.set_var_register(x, vy)?;
-    }
-    memory.set_var_register(0xF, 0b00000001u8 & vx)?;
-    memory.set_var_register(x, vx >> 1)
+#[cfg(feature = "tui")]
+fn run_terminal(
+    emulation: &mut Emulation,
+    rom_path: &Path,
+    settings: &Settings,
+    tone: Tone,
+) -> Result<(), Chip8Error> {
+    let keymap = keymap(rom_path, settings)?;
+    let mut terminal = Terminal::new(settings.palette.unwrap(), keymap, settings.hotkeys.clone())?;
+    run_with_sound(emulation, &mut terminal, rom_path, settings, tone)
 }
 
-fn shift_left(
-    memory: &mut Memory,
-    x: u8,
-    vx: u8,
-    vy: u8,
-    old_instructions: bool,
-) -> Result<(), String> {
-    if old_instructions {
-        memory.set_var_register(x, vy)?;
-    }
-    memory.set_var_register(0xF, 0b10000000u8 & vx)?;
-    memory.set_var_register(x, vx << 1)
+#[cfg(not(feature = "tui"))]
+fn run_terminal(_: &mut Emulation, _: &Path, _: &Settings, _: Tone) -> Result<(), Chip8Error> {
+    Err(Chip8Error::Config {
+        message: "built without the terminal frontend, rebuild with --features tui".to_string(),
+    })
 }
 
-fn sub_x_y(memory: &mut Memory, x: u8, vx: u8, vy: u8) -> Result<(), String> {
-    let res = vx as i32 - vy as i32;
-    if res > 0 {
-        memory.set_var_register(0xF, 1)?;
-        memory.set_var_register(x, res as u8)
-    } else {
-        memory.set_var_register(0xF, 0)?;
-        memory.set_var_register(x, res as u8)
+// sfml plays the beep and XO-CHIP patterns on its audio thread, also without a window
+#[cfg(feature = "sfml")]
+fn run_with_sound(
+    emulation: &mut Emulation,
+    frontend: &mut impl Frontend,
+    rom_path: &Path,
+    settings: &Settings,
+    tone: Tone,
+) -> Result<(), Chip8Error> {
+    let mut beeper_stream = BeeperStream::new(Synth::new(tone, SAMPLE_RATE));
+    let mut beeper = beeper_stream.beeper();
+    let mut beeper_player = SoundStreamPlayer::new(&mut beeper_stream);
+    if !settings.mute.unwrap() {
+        beeper_player.play();
     }
+    session::run(emulation, frontend, &mut beeper, rom_path, settings)
 }
 
-fn sub_y_x(memory: &mut Memory, x: u8, vx: u8, vy: u8) -> Result<(), String> {
-    let res = vy as i32 - vx as i32;
-    if res > 0 {
-        memory.set_var_register(0xF, 1)?;
-        memory.set_var_register(x, res as u8)
-    } else {
-        memory.set_var_register(0xF, 0)?;
-        memory.set_var_register(x, (res + 255) as u8)
-    }
+// without sfml the terminal bell is all there is
+#[cfg(all(feature = "tui", not(feature = "sfml")))]
+fn run_with_sound(
+    emulation: &mut Emulation,
+    frontend: &mut impl Frontend,
+    rom_path: &Path,
+    settings: &Settings,
+    _: Tone,
+) -> Result<(), Chip8Error> {
+    let mut bell = Bell::new(settings.mute.unwrap());
+    session::run(emulation, frontend, &mut bell, rom_path, settings)
 }
 
-fn add(memory: &mut Memory, x: u8, vx: u8, vy: u8) -> Result<(), String> {
-    let res = vx as usize + vy as usize;
-    if res > 255 {
-        memory.set_var_register(0xF, 1)?;
-    } else {
-        memory.set_var_register(0xF, 0)?;
-    }
-    memory.set_var_register(x, res as u8)
+#[cfg(any(feature = "sfml", feature = "tui"))]
+fn keymap(rom_path: &Path, settings: &Settings) -> Result<Keymap, Chip8Error> {
+    let rom_name = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    Keymap::from_arg(settings.keymap.as_ref().unwrap(), &rom_name)
 }
 
-fn skip_if_neq(x: u8, y: u8, memory: &mut Memory) -> Result<(), String> {
-    let vx = memory.get_var_register(x)?;
-    let vy = memory.get_var_register(y)?;
-    if vx != vy {
-        memory.increment_pc();
+// as fast as possible, without input
+fn run_headless(emulation: &mut Emulation) -> Result<(), Chip8Error> {
+    while !emulation.finished() {
+        emulation.run_frame()?;
     }
     Ok(())
 }
 
-fn skip_if_eq(x: u8, y: u8, memory: &mut Memory) -> Result<(), String> {
-    let vx = memory.get_var_register(x)?;
-    let vy = memory.get_var_register(y)?;
-    if vx == vy {
-        memory.increment_pc();
-    }
+fn assemble_source(matches: &ArgMatches) -> Result<(), Chip8Error> {
+    let path = Path::new(matches.get_one::<String>("path").unwrap());
+    let rom = assemble(&fs::read_to_string(path)?)?;
+    let output = match matches.get_one::<String>("output") {
+        Some(output) => PathBuf::from(output),
+        None => path.with_extension("ch8"),
+    };
+    fs::write(output, rom)?;
     Ok(())
 }
 
-fn skip_if_neq_im(x: u8, nn: u8, memory: &mut Memory) -> Result<(), String> {
-    let vx = memory.get_var_register(x)?;
-    if vx != nn {
-        memory.increment_pc();
-    }
+fn disassemble_rom(matches: &ArgMatches) -> Result<(), Chip8Error> {
+    let rom = fs::read(matches.get_one::<String>("path").unwrap())?;
+    let mode = matches.get_one::<String>("mode").unwrap().parse().unwrap();
+    let quirks = match matches.get_one::<String>("quirks") {
+        Some(preset) => Quirks::preset(preset).unwrap(),
+        None => Quirks::for_mode(mode),
+    };
+    let syntax = matches
+        .get_one::<String>("syntax")
+        .unwrap()
+        .parse()
+        .unwrap();
+    print!("{}", disassemble(&rom, mode, quirks, syntax));
     Ok(())
 }
 
-fn skip_if_eq_im(x: u8, nn: u8, memory: &mut Memory) -> Result<(), String> {
-    let vx = memory.get_var_register(x)?;
-    if vx == nn {
-        memory.increment_pc();
+fn dump_config(matches: &ArgMatches) -> Result<(), Chip8Error> {
+    let path = matches.get_one::<String>("path").map(Path::new);
+    let rom = path.map(|path| read_rom(matches, path)).transpose()?;
+    let rom_info = match &rom {
+        Some(rom) if !matches.get_flag("no-detect") => detect_rom(matches, rom)?,
+        _ => None,
+    };
+    let replay = replay_movie(matches)?;
+    let layers = settings_layers(
+        matches,
+        path.zip(rom.as_deref()),
+        rom_info.as_ref(),
+        replay.as_ref(),
+    )?;
+    println!("# layers, later ones take precedence:");
+    for (name, _) in &layers {
+        println!("#   {}", name);
     }
+    let mut settings = merge_layers(&layers);
+    settings.resolve_quirks();
+    print!("{}", settings.to_toml());
     Ok(())
 }
 
-fn load_rom(memory: &mut Memory, path: &str) -> Result<(), String> {
-    let rom = match fs::read(path) {
-        Ok(rom) => rom,
-        Err(err) => return Err(format!("Couldn't read rom file: \n {}", err)),
-    };
-    let pc = memory.pc();
-    memory.write_ram(pc, &rom);
-    Ok(())
+fn read_rom(matches: &ArgMatches, path: &Path) -> Result<Vec<u8>, Chip8Error> {
+    if matches.get_flag("source") {
+        assemble(&fs::read_to_string(path)?)
+    } else {
+        Ok(fs::read(path)?)
+    }
 }
 
-fn add_var_register(memory: &mut Memory, x: u8, nn: u8) -> Result<(), String> {
-    let curr_var = memory.get_var_register(x)?;
-    let result = (nn as usize) + (curr_var as usize);
-    memory.set_var_register(x, result as u8)?;
-    Ok(())
+fn replay_movie(matches: &ArgMatches) -> Result<Option<Movie>, Chip8Error> {
+    matches
+        .get_one::<String>("replay")
+        .map(|path| Movie::load(Path::new(path)))
+        .transpose()
 }
 
-fn zero_instructions(op_code: u16, screen: &mut Screen, memory: &mut Memory) -> Result<(), String> {
-    match op_code {
-        0x00E0u16 => screen.clear(), // 00E0: clear screen
-        0x00EEu16 => {
-            // 00EE: return from subroutine
-            let adress = memory.pop_stack()?;
-            memory.jump_pc(adress)
+// precedence: replayed movie > command line > [roms.*] sections of the config file > rom database
+// > config file > defaults
+fn settings_layers(
+    matches: &ArgMatches,
+    rom: Option<(&Path, &[u8])>,
+    rom_info: Option<&RomInfo>,
+    replay: Option<&Movie>,
+) -> Result<Vec<(String, Settings)>, Chip8Error> {
+    let mut layers = vec![("defaults".to_string(), Settings::defaults())];
+    // the default config file is optional, one given with --config is not
+    let config_path = match matches.get_one::<String>("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => ConfigFile::default_path().filter(|path| path.exists()),
+    };
+    let config = match &config_path {
+        Some(path) => Some(ConfigFile::load(path)?),
+        None => None,
+    };
+    if let (Some(path), Some(config)) = (&config_path, &config) {
+        layers.push((path.display().to_string(), config.settings.clone()));
+    }
+    if let Some(info) = rom_info {
+        layers.push((
+            format!("rom database: {}", info.title),
+            Settings::from_rom_info(info),
+        ));
+    }
+    if let (Some(path), Some(config), Some((rom_path, rom))) = (&config_path, &config, rom) {
+        let name = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+        for (section, settings) in config.rom_sections(&name, &sha1(rom)) {
+            layers.push((
+                format!("[roms.{}] in {}", section, path.display()),
+                settings.clone(),
+            ));
         }
-        _ => return Err("Invalid op code!".to_string()),
     }
-    Ok(())
+    layers.push(("command line".to_string(), settings_from_matches(matches)));
+    // mode, speed and quirks of the recording, anything else can still differ
+    if let (Some(path), Some(replay)) = (matches.get_one::<String>("replay"), replay) {
+        layers.push((format!("movie {}", path), replay.settings.clone()));
+    }
+    Ok(layers)
 }
 
-fn draw_sprite(
-    x: u8,
-    y: u8,
-    n: u8,
-    memory: &mut Memory,
-    screen: &mut Screen,
-) -> Result<(), String> {
-    let index_register = memory.index_register();
-    let x_off = memory.get_var_register(x)? % 64;
-    let y_off = memory.get_var_register(y)? % 32;
-    let mut x_cord;
-    let mut y_cord;
-    let mut row_sprite_bits;
-    let mut vf = 0u8;
-    let mut new_pixel;
-    let mut curr_pixel;
+fn merge_layers(layers: &[(String, Settings)]) -> Settings {
+    let mut settings = Settings::default();
+    for (_, layer) in layers {
+        settings.merge(layer);
+    }
+    settings
+}
 
-    for row in 0..n {
-        row_sprite_bits = memory.read_ram_cell(index_register + row as u16);
-        y_cord = y_off + row;
+fn detect_rom(matches: &ArgMatches, rom: &[u8]) -> Result<Option<RomInfo>, Chip8Error> {
+    let database = match matches.get_one::<String>("database") {
+        Some(dir) => Database::load(Path::new(dir))?,
+        None => Database::bundled(),
+    };
+    Ok(database.lookup(rom))
+}
 
-        for col in 0..8u8 {
-            x_cord = x_off + col;
-            new_pixel = (row_sprite_bits & (128u8 >> col)) != 0;
-            if new_pixel {
-                curr_pixel = screen.get_pixel(x_cord, y_cord)?;
-                if curr_pixel {
-                    vf = 1
-                }
-                screen.set_pixel(x_cord, y_cord, curr_pixel ^ new_pixel);
-            }
-            if x_cord >= 63 {
-                break;
-            }
-        }
-        if y_cord >= 31 {
-            break;
-        }
+fn describe_rom(info: &RomInfo) -> String {
+    let mut description = format!("Detected {} ({})\n", info.title, info.platform);
+    if !info.keys.is_empty() {
+        let keys: Vec<String> = info
+            .keys
+            .iter()
+            .map(|(name, key)| format!("{}: {:X}", name, key))
+            .collect();
+        description += &format!("Keys: {}\n", keys.join(", "));
     }
-    memory.set_var_register(0xF, vf)?;
-    screen.draw();
-    Ok(())
+    description
 }
 
-fn call_subroutine(nnn: u16, memory: &mut Memory) {
-    let pc = memory.pc();
-    memory.push_stack(pc);
-    memory.jump_pc(nnn)
+// the flags given on the command line
+fn settings_from_matches(matches: &ArgMatches) -> Settings {
+    let quirks = match matches.get_one::<String>("quirks") {
+        Some(preset) => Some(preset.clone()),
+        None if matches.get_flag("legacy") => Some("cosmac-vip".to_string()),
+        None => None,
+    };
+    let frequency = matches.get_one::<f32>("frequency").map(|frequency| {
+        let ipf = (frequency / 60.0).round().max(1.0) as u32;
+        eprintln!("warning: --frequency is deprecated, use --ipf {}", ipf);
+        ipf
+    });
+    let ipf = matches.get_one::<u32>("ipf").copied().or(frequency);
+    // --ipf turns off VIP timing picked by a config file
+    let vip_timing = match (matches.get_flag("vip-timing"), ipf) {
+        (true, _) => Some(true),
+        (false, Some(_)) => Some(false),
+        (false, None) => None,
+    };
+    Settings {
+        mode: matches
+            .get_one::<String>("mode")
+            .map(|mode| mode.parse().unwrap()),
+        ipf,
+        vip_timing,
+        quirks,
+        shift_quirk: matches.get_one::<bool>("shift-quirk").copied(),
+        memory_quirk: matches.get_one::<bool>("memory-quirk").copied(),
+        jump_quirk: matches.get_one::<bool>("jump-quirk").copied(),
+        vf_reset: matches.get_one::<bool>("vf-reset").copied(),
+        clip_sprites: matches.get_one::<bool>("clip-sprites").copied(),
+        display_wait: matches.get_one::<bool>("display-wait").copied(),
+        index_overflow: matches.get_one::<bool>("index-overflow").copied(),
+        palette: matches.get_one::<Palette>("palette").copied(),
+        window_size: matches.get_one::<WindowSize>("window-size").copied(),
+        scale: matches.get_one::<u32>("scale").copied(),
+        keymap: matches.get_one::<String>("keymap").cloned(),
+        waveform: matches
+            .get_one::<String>("waveform")
+            .map(|waveform| waveform.parse().unwrap()),
+        tone: matches.get_one::<f32>("tone").copied(),
+        volume: matches.get_one::<f32>("volume").copied(),
+        mute: matches.get_flag("mute").then_some(true),
+        rewind: matches.get_one::<u32>("rewind").copied(),
+        hotkeys: Hotkeys::default(),
+    }
 }
@@ -1,16 +1,42 @@
+use crate::error::{Chip8Error, Fault};
+use crate::state::{invalid, StateReader, StateWriter};
+
+const STACK_SIZE: usize = 16;
+pub const FONT_ADDRESS: u16 = 0x0050;
+pub const BIG_FONT_ADDRESS: u16 = 0x00A0;
+pub const RAM_SIZE: usize = 0x1000;
+pub const XO_RAM_SIZE: usize = 0x10000;
+
+// A read or write of a ram cell by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamAccess {
+    pub address: u16,
+    pub write: bool,
+}
+
 pub struct Memory {
-    ram: [u8; 4096],
+    ram: Vec<u8>,
     stack: Vec<u16>,
     index_register: u16,
     delay_register: u8,
     sound_register: u8,
     program_counter: u16,
     var_registers: [u8; 16],
+    flag_registers: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    access_log: Option<Vec<RamAccess>>,
+    register_log: u16,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        let mut ram = [0; 4096];
+        Self::with_size(RAM_SIZE)
+    }
+
+    // XO-CHIP programs get the full 64KiB address space
+    pub fn with_size(size: usize) -> Self {
+        let mut ram = vec![0; size];
 
         let fonts = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -31,9 +57,30 @@ impl Memory {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
 
-        for i in 0..fonts.len() {
-            ram[0x0050 + i] = fonts[i];
-        }
+        // SUPER-CHIP 8x10 hex font
+        let big_fonts = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+
+        let font_address = FONT_ADDRESS as usize;
+        let big_font_address = BIG_FONT_ADDRESS as usize;
+        ram[font_address..font_address + fonts.len()].copy_from_slice(&fonts);
+        ram[big_font_address..big_font_address + big_fonts.len()].copy_from_slice(&big_fonts);
 
         Memory {
             ram,
@@ -42,6 +89,11 @@ impl Memory {
             delay_register: 0,
             sound_register: 0,
             var_registers: [0; 16],
+            flag_registers: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: 64,
+            access_log: None,
+            register_log: 0,
             program_counter: 0x200, // start adress
         }
     }
@@ -54,65 +106,156 @@ impl Memory {
         self.index_register
     }
 
-    pub fn set_var_register(&mut self, id: u8, var: u8) -> Result<(), String> {
+    pub fn set_var_register(&mut self, id: u8, var: u8) -> Result<(), Fault> {
         if id > 15 {
-            return Err(format!(
-                "Var register id is out of range, must be 0x0-0xF id: {}",
-                id
-            ));
+            return Err(Fault::InvalidRegister { register: id });
         }
         self.var_registers[id as usize] = var;
+        if self.access_log.is_some() {
+            self.register_log |= 1 << id;
+        }
         Ok(())
     }
 
-    pub fn get_var_register(&self, id: u8) -> Result<u8, String> {
+    pub fn get_var_register(&self, id: u8) -> Result<u8, Fault> {
         if id > 15 {
-            return Err(format!(
-                "Var register id is out of range, must be 0x0-0xF id: {}",
-                id
-            ));
+            return Err(Fault::InvalidRegister { register: id });
         }
         Ok(self.var_registers[id as usize])
     }
 
+    // SUPER-CHIP RPL user flags, persisted by FX75 and restored by FX85
+    pub fn set_flag_register(&mut self, id: u8, var: u8) -> Result<(), Fault> {
+        if id > 15 {
+            return Err(Fault::InvalidRegister { register: id });
+        }
+        self.flag_registers[id as usize] = var;
+        Ok(())
+    }
+
+    pub fn get_flag_register(&self, id: u8) -> Result<u8, Fault> {
+        if id > 15 {
+            return Err(Fault::InvalidRegister { register: id });
+        }
+        Ok(self.flag_registers[id as usize])
+    }
+
+    pub fn var_registers(&self) -> &[u8; 16] {
+        &self.var_registers
+    }
+
+    // return addresses, innermost call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
     pub fn jump_pc(&mut self, adress: u16) {
         self.program_counter = adress;
     }
 
     pub fn increment_pc(&mut self) {
-        self.program_counter += 2;
+        self.program_counter = self.program_counter.wrapping_add(2);
     }
 
     pub fn pc(&self) -> u16 {
         self.program_counter
     }
 
-    pub fn pop_stack(&mut self) -> Result<u16, String> {
+    pub fn pop_stack(&mut self) -> Result<u16, Fault> {
         match self.stack.pop() {
             Some(val) => Ok(val),
-            None => return Err("pop called on empty stack".to_string()),
+            None => Err(Fault::StackUnderflow),
         }
     }
 
-    pub fn push_stack(&mut self, adress: u16) {
-        self.stack.push(adress)
+    pub fn push_stack(&mut self, adress: u16) -> Result<(), Fault> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+        self.stack.push(adress);
+        Ok(())
     }
 
-    pub fn fetch_instruction(&self) -> (u8, u8) {
-        (
-            self.ram[self.program_counter as usize],
-            self.ram[self.program_counter as usize + 1],
-        )
+    pub fn fetch_instruction(&self) -> Result<(u8, u8), Fault> {
+        let pc = self.program_counter as usize;
+        match self.ram.get(pc..pc + 2) {
+            Some(cells) => Ok((cells[0], cells[1])),
+            None => Err(Fault::OutOfBounds { address: pc + 1 }),
+        }
     }
 
-    pub fn write_ram(&mut self, address: u16, mem: &[u8]) {
-        for i in 0..mem.len() {
-            self.ram[i + address as usize] = mem[i];
+    pub fn write_ram(&mut self, address: u16, mem: &[u8]) -> Result<(), Fault> {
+        let address = address as usize;
+        match self.ram.get_mut(address..address + mem.len()) {
+            Some(cells) => cells.copy_from_slice(mem),
+            None => {
+                return Err(Fault::OutOfBounds {
+                    address: address + mem.len().saturating_sub(1),
+                })
+            }
+        }
+        for offset in 0..mem.len() {
+            self.log_access((address + offset) as u16, true);
+        }
+        Ok(())
+    }
+
+    // read a cell on behalf of the running program, unlike read_ram_cell it shows up in the access log
+    // I + offset past the end of ram faults instead of wrapping around, so the address is a usize
+    pub fn read_ram(&mut self, address: usize) -> Result<u8, Fault> {
+        match self.ram.get(address) {
+            Some(cell) => {
+                let cell = *cell;
+                self.log_access(address as u16, false);
+                Ok(cell)
+            }
+            None => Err(Fault::OutOfBounds { address }),
         }
     }
 
-    pub fn read_ram_cell(&self, address: u16) -> u8 {
-        self.ram[address as usize]
+    pub fn read_ram_cell(&self, address: u16) -> Result<u8, Fault> {
+        match self.ram.get(address as usize) {
+            Some(cell) => Ok(*cell),
+            None => Err(Fault::OutOfBounds {
+                address: address as usize,
+            }),
+        }
+    }
+
+    pub fn ram_size(&self) -> usize {
+        self.ram.len()
+    }
+
+    // start or stop recording the ram accesses of the program (for watchpoints)
+    // also logs which var registers get written, for the debugger's watchpoints
+    pub fn set_access_logging(&mut self, enabled: bool) {
+        if enabled != self.access_log.is_some() {
+            self.access_log = enabled.then(Vec::new);
+            self.register_log = 0;
+        }
+    }
+
+    pub fn access_logging(&self) -> bool {
+        self.access_log.is_some()
+    }
+
+    // ram accesses since the last call
+    pub fn take_access_log(&mut self) -> Vec<RamAccess> {
+        self.access_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    // var registers written since the last call, bit x => VX
+    pub fn take_register_log(&mut self) -> u16 {
+        std::mem::take(&mut self.register_log)
+    }
+
+    fn log_access(&mut self, address: u16, write: bool) {
+        if let Some(access_log) = &mut self.access_log {
+            access_log.push(RamAccess { address, write });
+        }
     }
 
     pub fn decrement_sound(&mut self) {
@@ -135,11 +278,86 @@ impl Memory {
         self.delay_register = register
     }
 
+    pub fn sound_register(&self) -> u8 {
+        self.sound_register
+    }
+
     pub fn set_sounds_register(&mut self, register: u8) {
         self.sound_register = register
     }
 
+    // XO-CHIP audio: 128 one bit samples played at 4000 * 2 ^ ((pitch - 64) / 48) Hz
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    pub fn set_audio_pattern(&mut self, pattern: [u8; 16]) {
+        self.audio_pattern = pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch
+    }
+
+    pub fn playback_rate(&self) -> f32 {
+        4000f32 * 2f32.powf((self.pitch as f32 - 64f32) / 48f32)
+    }
+
     pub fn decrement_pc(&mut self) {
-        self.program_counter -= 2
+        self.program_counter = self.program_counter.wrapping_sub(2);
+    }
+
+    pub fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.ram);
+        writer.u8(self.stack.len() as u8);
+        for address in &self.stack {
+            writer.u16(*address);
+        }
+        writer.u16(self.index_register);
+        writer.u8(self.delay_register);
+        writer.u8(self.sound_register);
+        writer.u16(self.program_counter);
+        writer.array(&self.var_registers);
+        writer.array(&self.flag_registers);
+        writer.array(&self.audio_pattern);
+        writer.u8(self.pitch);
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Result<Self, Chip8Error> {
+        let ram = reader.bytes()?.to_vec();
+        if ram.len() != RAM_SIZE && ram.len() != XO_RAM_SIZE {
+            return Err(invalid(format!("unexpected ram size {}", ram.len())));
+        }
+        let stack_size = reader.u8()? as usize;
+        if stack_size > STACK_SIZE {
+            return Err(invalid(format!("stack holds {} addresses", stack_size)));
+        }
+        let stack = (0..stack_size)
+            .map(|_| reader.u16())
+            .collect::<Result<_, _>>()?;
+        Ok(Memory {
+            ram,
+            stack,
+            index_register: reader.u16()?,
+            delay_register: reader.u8()?,
+            sound_register: reader.u8()?,
+            program_counter: reader.u16()?,
+            var_registers: reader.array()?,
+            flag_registers: reader.array()?,
+            audio_pattern: reader.array()?,
+            pitch: reader.u8()?,
+            access_log: None,
+            register_log: 0,
+        })
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,37 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Platform whose instruction set the interpreter implements
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "chip8" => Ok(Mode::Chip8),
+            "schip" => Ok(Mode::SuperChip),
+            "xochip" => Ok(Mode::XoChip),
+            _ => Err(format!(
+                "unknown mode: {}, expected chip8, schip or xochip",
+                mode
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Chip8 => write!(f, "chip8"),
+            Mode::SuperChip => write!(f, "schip"),
+            Mode::XoChip => write!(f, "xochip"),
+        }
+    }
+}
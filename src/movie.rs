@@ -0,0 +1,171 @@
+use crate::config::Settings;
+use crate::database::sha1;
+use crate::error::Chip8Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/*
+Movie:
+    - keypad input of every frame of a run, replayed to repeat the run exactly
+    - saved as TOML, keys lists [frame, key flags] whenever the pressed keys changed:
+        sha1 = "0b8a4bb2..."
+        seed = 1234
+        frames = 600
+        keys = [[87, 32], [93, 0]]
+        [settings]
+        mode = "schip"
+        ipf = 30
+    sha1:
+        - sha1 of the rom the movie was recorded with
+    seed:
+        - seed of the CXNN random numbers
+    frames:
+        - number of recorded frames
+    keys:
+        - (first frame, key flags (bit n => key n)) of every change of the pressed keys
+    settings:
+        - mode, speed and quirks of the recording, see Settings::machine
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Movie {
+    pub sha1: String,
+    pub seed: u32,
+    pub frames: u64,
+    keys: Vec<(u64, u16)>,
+    pub settings: Settings,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u32, settings: &Settings) -> Self {
+        Movie {
+            sha1: sha1(rom),
+            seed,
+            frames: 0,
+            keys: vec![],
+            settings: settings.machine(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, Chip8Error> {
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Movie::from_toml(&text))
+            .map_err(|message| Chip8Error::InvalidMovie {
+                message: format!("{}: {}", path.display(), message),
+            })
+    }
+
+    pub fn from_toml(text: &str) -> Result<Movie, String> {
+        let movie: Movie = toml::from_str(text).map_err(|err| err.message().to_string())?;
+        movie.settings.check()?;
+        if movie.keys.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err("the frames of keys have to increase".to_string());
+        }
+        Ok(movie)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    // a movie only repeats the run with the rom it was recorded with
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let rom_sha1 = sha1(rom);
+        if rom_sha1 != self.sha1 {
+            return Err(Chip8Error::InvalidMovie {
+                message: format!("recorded with the rom {}, not with {}", self.sha1, rom_sha1),
+            });
+        }
+        Ok(())
+    }
+
+    // called before every frame, a frame recorded again (after rewinding) drops everything after it
+    pub fn record(&mut self, frame: u64, key_flags: u16) {
+        let kept = self.keys.partition_point(|(start, _)| *start < frame);
+        self.keys.truncate(kept);
+        if self.key_flags(frame) != key_flags {
+            self.keys.push((frame, key_flags));
+        }
+        self.frames = frame + 1;
+    }
+
+    // keys pressed during a frame
+    pub fn key_flags(&self, frame: u64) -> u16 {
+        match self.keys.partition_point(|(start, _)| *start <= frame) {
+            0 => 0,
+            changes => self.keys[changes - 1].1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mode;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn movie() -> Movie {
+        let settings = Settings {
+            mode: Some(Mode::SuperChip),
+            ipf: Some(30),
+            ..Settings::default()
+        };
+        Movie::new(&ROM, 1234, &settings)
+    }
+
+    // keys held in frames 0..10: none, key 5 in 3..6, keys 5 and 7 in 6..8, none afterwards
+    fn recorded() -> Movie {
+        let mut movie = movie();
+        for frame in 0..10 {
+            let key_flags = match frame {
+                3..=5 => 1 << 5,
+                6..=7 => 1 << 5 | 1 << 7,
+                _ => 0,
+            };
+            movie.record(frame, key_flags);
+        }
+        movie
+    }
+
+    #[test]
+    fn replay_returns_the_recorded_keys() {
+        let movie = recorded();
+        assert_eq!(movie.frames, 10);
+        assert_eq!(movie.keys, [(3, 0x20), (6, 0xA0), (8, 0)]);
+        let replayed: Vec<u16> = (0..10).map(|frame| movie.key_flags(frame)).collect();
+        assert_eq!(replayed, [0, 0, 0, 0x20, 0x20, 0x20, 0xA0, 0xA0, 0, 0]);
+    }
+
+    #[test]
+    fn recording_after_a_rewind_drops_the_rewound_frames() {
+        let mut movie = recorded();
+        // rewound to frame 5, key 1 is pressed instead
+        movie.record(5, 1 << 1);
+        assert_eq!(movie.frames, 6);
+        assert_eq!(movie.keys, [(3, 0x20), (5, 0x02)]);
+        movie.record(6, 0);
+        assert_eq!(movie.key_flags(4), 0x20);
+        assert_eq!(movie.key_flags(5), 0x02);
+        assert_eq!(movie.key_flags(6), 0);
+        assert_eq!(movie.key_flags(9), 0);
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let movie = recorded();
+        assert_eq!(Movie::from_toml(&movie.to_toml()).unwrap(), movie);
+        assert!(movie.check_rom(&ROM).is_ok());
+        assert!(movie.check_rom(&[0x00, 0xE0]).is_err());
+    }
+
+    #[test]
+    fn keys_out_of_order_are_rejected() {
+        let text = movie()
+            .to_toml()
+            .replace("keys = []", "keys = [[5, 1], [5, 2]]");
+        assert!(Movie::from_toml(&text).is_err());
+    }
+}
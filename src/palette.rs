@@ -0,0 +1,71 @@
+use std::fmt;
+use std::str::FromStr;
+
+/*
+Palette:
+    colors:
+        - rgb color for each pixel color index
+        - 0: background, 1: plane 0, 2: plane 1, 3: both planes (XO-CHIP)
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    pub fn color(&self, index: u8) -> [u8; 3] {
+        self.colors[index as usize & 0b11]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: [
+                [0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA],
+                [0x55, 0x55, 0x55],
+            ],
+        }
+    }
+}
+
+// comma separated "#RRGGBB" colors, starting with the background, missing ones keep their default
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(colors: &str) -> Result<Self, Self::Err> {
+        let colors: Vec<&str> = colors.split(',').map(str::trim).collect();
+        if colors.len() > 4 {
+            return Err(format!("{} colors given, a palette has 4", colors.len()));
+        }
+        let mut palette = Palette::default();
+        for (color, hex) in palette.colors.iter_mut().zip(colors) {
+            *color = parse_hex_color(hex)
+                .ok_or_else(|| format!("invalid color: {}, expected #RRGGBB", hex))?;
+        }
+        Ok(palette)
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let colors: Vec<String> = self
+            .colors
+            .iter()
+            .map(|[r, g, b]| format!("#{:02X}{:02X}{:02X}", r, g, b))
+            .collect();
+        write!(f, "{}", colors.join(","))
+    }
+}
+
+// "#RRGGBB"
+pub fn parse_hex_color(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
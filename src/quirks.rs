@@ -0,0 +1,108 @@
+use crate::mode::Mode;
+
+pub const PRESETS: [&str; 5] = [
+    "cosmac-vip",
+    "chip48",
+    "schip-legacy",
+    "schip-modern",
+    "xo-chip",
+];
+
+/*
+Quirks:
+    shift_uses_vy:
+        - 8XY6/8XYE shift var[y] into var[x] instead of shifting var[x] in place
+    increment_index:
+        - FX55/FX65 leave I pointing after the last stored/loaded register
+    jump_uses_vx:
+        - BXNN jumps to XNN + var[x] instead of BNNN jumping to NNN + var[0]
+    vf_reset:
+        - 8XY1/8XY2/8XY3 reset var[F] to 0
+    clip_sprites:
+        - sprites are clipped at the screen edges instead of wrapping around
+    display_wait:
+        - DXYN waits for the next 60Hz frame before execution continues
+    index_overflow:
+        - FX1E sets var[F] to 1 when I passes 0xFFF, only some later interpreters do, so no preset
+          sets it (never in XO-CHIP mode, I has 16 bits there)
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,
+    pub increment_index: bool,
+    pub jump_uses_vx: bool,
+    pub vf_reset: bool,
+    pub clip_sprites: bool,
+    pub display_wait: bool,
+    pub index_overflow: bool,
+}
+
+impl Quirks {
+    pub fn preset(name: &str) -> Option<Quirks> {
+        let quirks = match name {
+            "cosmac-vip" => Quirks {
+                shift_uses_vy: true,
+                increment_index: true,
+                jump_uses_vx: false,
+                vf_reset: true,
+                clip_sprites: true,
+                display_wait: true,
+                index_overflow: false,
+            },
+            "chip48" => Quirks {
+                shift_uses_vy: false,
+                increment_index: true,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                index_overflow: false,
+            },
+            "schip-legacy" => Quirks {
+                shift_uses_vy: false,
+                increment_index: false,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: true,
+                index_overflow: false,
+            },
+            "schip-modern" => Quirks {
+                shift_uses_vy: false,
+                increment_index: false,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                index_overflow: false,
+            },
+            "xo-chip" => Quirks {
+                shift_uses_vy: true,
+                increment_index: true,
+                jump_uses_vx: false,
+                vf_reset: false,
+                clip_sprites: false,
+                display_wait: false,
+                index_overflow: false,
+            },
+            _ => return None,
+        };
+        Some(quirks)
+    }
+
+    // quirks used when no preset is given
+    pub fn for_mode(mode: Mode) -> Quirks {
+        match mode {
+            Mode::Chip8 => Quirks::preset("chip48"),
+            Mode::SuperChip => Quirks::preset("schip-modern"),
+            Mode::XoChip => Quirks::preset("xo-chip"),
+        }
+        .unwrap()
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::for_mode(Mode::Chip8)
+    }
+}
@@ -0,0 +1,47 @@
+use rand::random;
+
+/*
+Random:
+    - random numbers of the CXNN instruction (SplitMix64), seeded so a run can be repeated exactly
+    - implemented here instead of taken from rand, movies have to replay the same way in every build
+    state:
+        - advanced by every number drawn, part of save states
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn with_seed(seed: u32) -> Self {
+        Random { state: seed as u64 }
+    }
+
+    // picks a random seed
+    pub fn new() -> Self {
+        Random::with_seed(random())
+    }
+
+    pub fn from_state(state: u64) -> Self {
+        Random { state }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        // the high bits are the best mixed
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new()
+    }
+}
@@ -0,0 +1,181 @@
+use crate::error::Chip8Error;
+use crate::machine::Chip8;
+use crate::timing::FRAME_RATE;
+use std::collections::VecDeque;
+
+// seconds of gameplay kept by default
+pub const REWIND_SECONDS: u32 = 30;
+
+/*
+Rewind:
+    - ring buffer of the save states of the last frames
+    latest:
+        - state of the most recent frame, uncompressed
+    deltas:
+        - for every older frame (newest first), the delta turning the next newer state into it
+        - states of consecutive frames hardly differ, so the deltas are a few bytes each
+    capacity:
+        - frames kept, the oldest is dropped once full
+*/
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn with_seconds(seconds: u32) -> Self {
+        Self::new((seconds * FRAME_RATE) as usize)
+    }
+
+    // remember the state after a frame
+    pub fn record(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+        if let Some(previous) = self.latest.replace(state) {
+            if self.capacity == 0 {
+                return;
+            }
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_back();
+            }
+            let delta = delta(self.latest.as_ref().unwrap(), &previous);
+            self.deltas.push_front(delta);
+        }
+    }
+
+    // go back one frame, false once the oldest kept frame is reached
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        let (Some(latest), Some(delta)) = (&mut self.latest, self.deltas.pop_front()) else {
+            return Ok(false);
+        };
+        *latest = apply_delta(latest, &delta);
+        chip8.load_state(latest)?;
+        Ok(true)
+    }
+
+    // frames that can be rewound
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // bytes held by the buffer
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+/*
+delta from `from` to `to`:
+    - length of `to` (u32), then runs of
+      unchanged bytes (u32), changed bytes (u32), the changed bytes xor `from`
+    - bytes past the end of `from` are xored with 0
+*/
+fn delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = (0..to.len())
+        .map(|index| to[index] ^ from.get(index).copied().unwrap_or_default())
+        .collect();
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(to.len() as u32).to_le_bytes());
+    let mut position = 0;
+    while position < xor.len() {
+        let unchanged = xor[position..]
+            .iter()
+            .take_while(|byte| **byte == 0)
+            .count();
+        let changed = xor[position + unchanged..]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .count();
+        delta.extend_from_slice(&(unchanged as u32).to_le_bytes());
+        delta.extend_from_slice(&(changed as u32).to_le_bytes());
+        let start = position + unchanged;
+        delta.extend_from_slice(&xor[start..start + changed]);
+        position = start + changed;
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let word = |offset: usize| u32::from_le_bytes(delta[offset..offset + 4].try_into().unwrap());
+    let length = word(0) as usize;
+    let mut to: Vec<u8> = (0..length)
+        .map(|index| from.get(index).copied().unwrap_or_default())
+        .collect();
+    let (mut offset, mut position) = (4, 0);
+    while offset < delta.len() {
+        let (unchanged, changed) = (word(offset) as usize, word(offset + 4) as usize);
+        offset += 8;
+        position += unchanged;
+        for (byte, xor) in to[position..position + changed]
+            .iter_mut()
+            .zip(&delta[offset..offset + changed])
+        {
+            *byte ^= xor;
+        }
+        position += changed;
+        offset += changed;
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Mode, Quirks};
+
+    #[test]
+    fn apply_delta_inverts_delta() {
+        let states: [&[u8]; 5] = [
+            &[1, 2, 3, 4, 5, 6],
+            &[1, 9, 3, 4, 0, 6],
+            &[1, 9, 3, 4, 0, 6, 7, 8],
+            &[0, 0],
+            &[],
+        ];
+        for from in states {
+            for to in states {
+                assert_eq!(apply_delta(from, &delta(from, to)), to);
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_bytes_are_skipped() {
+        let from = vec![7; 1000];
+        let mut to = from.clone();
+        to[500] = 8;
+        let delta = delta(&from, &to);
+        assert!(delta.len() < 32, "{} bytes", delta.len());
+        assert_eq!(apply_delta(&from, &delta), to);
+    }
+
+    #[test]
+    fn oldest_frames_are_dropped_when_full() {
+        let mut chip8 = Chip8::new(Mode::Chip8, Quirks::default());
+        let mut rewind = Rewind::new(3);
+        for frame in 0..6 {
+            chip8.memory_mut().set_var_register(0, frame).unwrap();
+            rewind.record(&chip8);
+        }
+        assert_eq!(rewind.len(), 3);
+        for frame in (2..5).rev() {
+            assert!(rewind.rewind(&mut chip8).unwrap());
+            assert_eq!(chip8.memory().var_registers()[0], frame);
+        }
+        assert!(rewind.is_empty());
+        assert!(!rewind.rewind(&mut chip8).unwrap());
+        assert_eq!(chip8.memory().var_registers()[0], 2);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::debug_console::DebugConsole;
+use crate::emulation::Emulation;
+use chip8::config::Settings;
+use chip8::frontend::{Display, Keypad, Speaker};
+use chip8::timing::FrameClock;
+use chip8::video::VideoRecorder;
+use chip8::{Chip8, Chip8Error, Mode};
+use std::fs;
+use std::path::Path;
+use std::thread::sleep;
+
+// Save state hotkey pressed in the frontend, with the selected slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateRequest {
+    Save(u8),
+    Load(u8),
+}
+
+// Window or terminal a rom is played in
+pub trait Frontend: Display + Keypad {
+    fn take_state_request(&mut self) -> Option<StateRequest>;
+
+    // true once for every press of the record hotkey
+    fn take_record_toggle(&mut self) -> bool;
+
+    // true while the rewind hotkey is held
+    fn rewinding(&self) -> bool;
+
+    // called every frame, for frontends showing more of the machine than its screen
+    fn show_machine(&mut self, _chip8: &Chip8) {}
+
+    fn show_message(&mut self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+// play in real time until the frontend is closed
+pub fn run(
+    emulation: &mut Emulation,
+    frontend: &mut impl Frontend,
+    speaker: &mut impl Speaker,
+    rom_path: &Path,
+    settings: &Settings,
+) -> Result<(), Chip8Error> {
+    let mut console = emulation.debugger.as_ref().map(|_| DebugConsole::new());
+    let mut frame_clock = FrameClock::new();
+
+    loop {
+        emulation.chip8.poll_keypad(frontend);
+        match frontend.take_state_request() {
+            Some(StateRequest::Load(_)) if emulation.playing_movie() => {
+                frontend.show_message("can't load a state while recording or replaying a movie")
+            }
+            // a missing or broken state file shouldn't end the session
+            Some(request) => match save_or_load_state(&mut emulation.chip8, request, rom_path) {
+                Ok(message) => frontend.show_message(&message),
+                Err(err) => frontend.show_message(&err.to_string()),
+            },
+            None => (),
+        }
+        if frontend.take_record_toggle() {
+            match toggle_recording(emulation, rom_path, settings) {
+                Ok(message) => frontend.show_message(&message),
+                Err(err) => frontend.show_message(&err.to_string()),
+            }
+        }
+        if let (Some(console), Some(debugger)) = (&mut console, &mut emulation.debugger) {
+            console.update(debugger, &mut emulation.chip8);
+            if console.quit() {
+                break;
+            }
+        }
+        if frontend.closed() || emulation.finished() {
+            break;
+        }
+        for _ in 0..frame_clock.frames_due() {
+            if emulation.finished() {
+                break;
+            }
+            if frontend.rewinding() {
+                emulation.rewind_frame()?;
+                continue;
+            }
+            if let Err(err) = emulation.run_frame() {
+                emulation.catch_fault(err)?;
+            }
+        }
+        emulation.chip8.present(frontend);
+        frontend.show_machine(&emulation.chip8);
+        emulation.chip8.play_sound(speaker);
+        sleep(frame_clock.until_next_frame());
+    }
+    Ok(())
+}
+
+// slot n of game.ch8 is game.state<n>
+fn save_or_load_state(
+    chip8: &mut Chip8,
+    request: StateRequest,
+    rom_path: &Path,
+) -> Result<String, Chip8Error> {
+    match request {
+        StateRequest::Save(slot) => {
+            let path = rom_path.with_extension(format!("state{}", slot));
+            fs::write(&path, chip8.save_state())?;
+            Ok(format!("saved state to {}", path.display()))
+        }
+        StateRequest::Load(slot) => {
+            let path = rom_path.with_extension(format!("state{}", slot));
+            chip8.load_state(&fs::read(&path)?)?;
+            Ok(format!("loaded state from {}", path.display()))
+        }
+    }
+}
+
+// recordings of game.ch8 are game-<n>.gif, numbered from 1 without overwriting older ones
+fn toggle_recording(
+    emulation: &mut Emulation,
+    rom_path: &Path,
+    settings: &Settings,
+) -> Result<String, Chip8Error> {
+    if let Some(video) = emulation.take_video() {
+        let frames = video.frames();
+        video.finish()?;
+        return Ok(format!("stopped recording after {} frames", frames));
+    }
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let path = (1..)
+        .map(|n| rom_path.with_file_name(format!("{}-{}.gif", stem, n)))
+        .find(|path| !path.exists())
+        .unwrap();
+    let video = VideoRecorder::create(
+        &path,
+        settings.palette.unwrap(),
+        settings.scale.unwrap(),
+        emulation.chip8.mode() != Mode::Chip8,
+    )?;
+    emulation.set_video(video);
+    Ok(format!("recording to {}", path.display()))
+}
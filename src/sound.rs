@@ -0,0 +1,69 @@
+use chip8::audio::{Sound, Synth};
+use chip8::frontend::Speaker;
+use sfml::audio::SoundStream;
+use sfml::system::Time;
+use std::sync::{Arc, Mutex};
+
+// samples handed to sfml per request, ~23ms at 44.1kHz
+const CHUNK_SIZE: usize = 1024;
+
+/*
+BeeperStream:
+    - sfml sound stream playing the synth on the audio thread
+    synth:
+        - generates the beep
+    sound:
+        - shared with the Beeper, what the emulation is currently playing
+    buffer:
+        - chunk of samples handed to sfml
+*/
+pub struct BeeperStream {
+    synth: Synth,
+    sound: Arc<Mutex<Sound>>,
+    buffer: Vec<i16>,
+}
+
+// Speaker handing the sound of every frame to a BeeperStream from the emulation thread
+pub struct Beeper {
+    sound: Arc<Mutex<Sound>>,
+}
+
+impl BeeperStream {
+    pub fn new(synth: Synth) -> Self {
+        BeeperStream {
+            synth,
+            sound: Arc::new(Mutex::new(Sound::Silence)),
+            buffer: vec![0; CHUNK_SIZE],
+        }
+    }
+
+    pub fn beeper(&self) -> Beeper {
+        Beeper {
+            sound: Arc::clone(&self.sound),
+        }
+    }
+}
+
+impl SoundStream for BeeperStream {
+    fn get_data(&mut self) -> (&mut [i16], bool) {
+        let sound = *self.sound.lock().unwrap();
+        self.synth.play(sound, &mut self.buffer);
+        (&mut self.buffer, true)
+    }
+
+    fn seek(&mut self, _offset: Time) {}
+
+    fn channel_count(&self) -> u32 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.synth.sample_rate()
+    }
+}
+
+impl Speaker for Beeper {
+    fn play(&mut self, sound: Sound) {
+        *self.sound.lock().unwrap() = sound
+    }
+}
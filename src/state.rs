@@ -0,0 +1,232 @@
+use crate::error::Chip8Error;
+use sha1_smol::Sha1;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+// bumped whenever the layout of a saved machine changes
+pub const STATE_VERSION: u16 = 3;
+const HEADER_SIZE: usize = MAGIC.len() + 2;
+const CHECKSUM_SIZE: usize = 20;
+
+/*
+StateWriter:
+    - serializes a machine into a save state: magic, version, payload, sha1 of everything before it
+    payload:
+        - little endian fields written by Chip8 / Memory / Framebuffer, in the order they are read back
+*/
+#[derive(Default)]
+pub struct StateWriter {
+    payload: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.payload.push(value)
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.payload.extend_from_slice(&value.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.payload.extend_from_slice(&value.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.payload.extend_from_slice(&value.to_le_bytes())
+    }
+
+    // fixed size data, the reader has to know the length
+    pub fn array(&mut self, bytes: &[u8]) {
+        self.payload.extend_from_slice(bytes)
+    }
+
+    // length prefixed data
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.array(bytes)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_SIZE + self.payload.len() + CHECKSUM_SIZE);
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        state.extend_from_slice(&self.payload);
+        let checksum = Sha1::from(&state).digest().bytes();
+        state.extend_from_slice(&checksum);
+        state
+    }
+}
+
+/*
+StateReader:
+    - reads back the payload of a StateWriter after checking magic, version and checksum
+    payload:
+        - the fields, without header and checksum
+    position:
+        - offset of the next field
+*/
+pub struct StateReader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(state: &'a [u8]) -> Result<Self, Chip8Error> {
+        if state.len() < HEADER_SIZE + CHECKSUM_SIZE || &state[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a chip8 save state"));
+        }
+        let version = u16::from_le_bytes([state[4], state[5]]);
+        if version != STATE_VERSION {
+            return Err(invalid(format!(
+                "version {} is not supported, expected {}",
+                version, STATE_VERSION
+            )));
+        }
+        let (content, checksum) = state.split_at(state.len() - CHECKSUM_SIZE);
+        if Sha1::from(content).digest().bytes() != checksum {
+            return Err(invalid("checksum mismatch, the file is corrupted"));
+        }
+        Ok(StateReader {
+            payload: &content[HEADER_SIZE..],
+            position: 0,
+        })
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Chip8Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(invalid(format!("{} is not a bool", value))),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Chip8Error> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Chip8Error> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Chip8Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Chip8Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], Chip8Error> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    // every field has to be consumed
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        if self.position != self.payload.len() {
+            return Err(invalid("unexpected data after the machine state"));
+        }
+        Ok(())
+    }
+
+    // fixed size data written with StateWriter::array
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], Chip8Error> {
+        let bytes = self
+            .payload
+            .get(self.position..self.position + length)
+            .ok_or_else(|| invalid("unexpected end of the machine state"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+}
+
+pub fn invalid(message: impl Into<String>) -> Chip8Error {
+    Chip8Error::InvalidState {
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chip8, Mode, Quirks};
+
+    // draws the font digits 0-F at random heights, then loops
+    const ROM: [u8; 22] = [
+        0x00, 0xE0, 0x60, 0x00, 0xC1, 0x1F, 0xF2, 0x29, 0xD0, 0x15, 0x70, 0x05, 0x72, 0x01, 0x32,
+        0x10, 0x12, 0x04, 0x12, 0x14, 0x12, 0x14,
+    ];
+
+    fn running_machine() -> Chip8 {
+        let mut chip8 = Chip8::new(Mode::SuperChip, Quirks::default());
+        chip8.load_rom_bytes(&ROM).unwrap();
+        chip8.set_seed(7);
+        chip8.run_frame(5).unwrap();
+        chip8
+    }
+
+    // change the state and sign it again, so only the change is wrong
+    fn resigned(state: &[u8], change: impl Fn(&mut Vec<u8>)) -> Vec<u8> {
+        let mut content = state[..state.len() - CHECKSUM_SIZE].to_vec();
+        change(&mut content);
+        let checksum = Sha1::from(&content).digest().bytes();
+        content.extend_from_slice(&checksum);
+        content
+    }
+
+    fn rejected(state: &[u8]) -> String {
+        let mut chip8 = Chip8::new(Mode::Chip8, Quirks::default());
+        match chip8.load_state(state) {
+            Err(Chip8Error::InvalidState { message }) => message,
+            result => panic!("expected an invalid state, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn load_restores_the_saved_machine() {
+        let mut original = running_machine();
+        let state = original.save_state();
+        let mut restored = Chip8::new(Mode::Chip8, Quirks::default());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.mode(), Mode::SuperChip);
+        // both continue the same way, random numbers included
+        original.run_frame(20).unwrap();
+        restored.run_frame(20).unwrap();
+        assert_eq!(restored.save_state(), original.save_state());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let state = resigned(&running_machine().save_state(), |state| state[0] = b'X');
+        assert_eq!(rejected(&state), "not a chip8 save state");
+        assert_eq!(rejected(b"C8"), "not a chip8 save state");
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let state = resigned(&running_machine().save_state(), |state| {
+            state[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes())
+        });
+        assert!(rejected(&state).starts_with("version"));
+    }
+
+    #[test]
+    fn corruption_is_rejected() {
+        let mut state = running_machine().save_state();
+        state[HEADER_SIZE + 10] ^= 1;
+        assert_eq!(rejected(&state), "checksum mismatch, the file is corrupted");
+    }
+}
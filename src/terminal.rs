@@ -0,0 +1,393 @@
+use crate::session::{Frontend, StateRequest};
+#[cfg(not(feature = "sfml"))]
+use chip8::audio::Sound;
+use chip8::config::Hotkeys;
+use chip8::framebuffer::Framebuffer;
+#[cfg(not(feature = "sfml"))]
+use chip8::frontend::Speaker;
+use chip8::frontend::{Display, Keypad};
+use chip8::keymap::Keymap;
+use chip8::palette::Palette;
+use chip8::Chip8;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// without key release events a key is held until it hasn't been repeated for this long
+const KEY_HOLD: Duration = Duration::from_millis(200);
+
+/*
+Terminal:
+    - draws the framebuffer with half blocks, one character shows two pixel rows
+    pixel_colors:
+        - color (0-3) of every pixel of the last presented framebuffer
+    resolution:
+        - (width,height) of the last presented framebuffer in pixels
+    drawn:
+        - pixel colors currently on the terminal, only changed characters are redrawn
+    status:
+        - lines below the screen currently on the terminal
+    held:
+        - when each CHIP-8 key was last pressed, None if released
+    rewind_held:
+        - when the rewind hotkey was last pressed
+    record_toggled:
+        - the record hotkey was pressed since the last take_record_toggle
+    releases:
+        - the terminal reports key releases, otherwise they are emulated with KEY_HOLD
+    enhanced:
+        - keyboard enhancement flags were pushed and have to be popped again
+    message:
+        - last message shown in the status lines
+*/
+pub struct Terminal {
+    pixel_colors: Vec<u8>,
+    resolution: (usize, usize),
+    drawn: Vec<u8>,
+    status: Vec<String>,
+    palette: Palette,
+    keymap: Keymap,
+    hotkeys: Hotkeys,
+    held: [Option<Instant>; 16],
+    rewind_held: Option<Instant>,
+    releases: bool,
+    enhanced: bool,
+    quit_flag: bool,
+    slot: u8,
+    state_request: Option<StateRequest>,
+    record_toggled: bool,
+    message: String,
+}
+
+impl Terminal {
+    pub fn new(palette: Palette, keymap: Keymap, hotkeys: Hotkeys) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        // lets terminals speaking the kitty keyboard protocol report key releases
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Terminal {
+            pixel_colors: vec![],
+            resolution: (0, 0),
+            drawn: vec![],
+            status: vec![],
+            palette,
+            keymap,
+            hotkeys,
+            held: [None; 16],
+            rewind_held: None,
+            releases: false,
+            enhanced,
+            quit_flag: false,
+            slot: 0,
+            state_request: None,
+            record_toggled: false,
+            message: "ctrl+c quits, alt+0-9 selects the save state slot".to_string(),
+        })
+    }
+
+    fn key_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            self.releases = true;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit_flag = true;
+            return;
+        }
+        if let (true, KeyCode::Char(digit @ '0'..='9')) =
+            (key.modifiers.contains(KeyModifiers::ALT), key.code)
+        {
+            if key.kind == KeyEventKind::Press {
+                self.slot = digit as u8 - b'0';
+                self.message = format!("save state slot {}", self.slot);
+            }
+            return;
+        }
+        let name = key_name(key.code);
+        let pressed = match key.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => Some(Instant::now()),
+            KeyEventKind::Release => None,
+        };
+        if key.kind == KeyEventKind::Press {
+            if self.hotkeys.save_state == name {
+                self.state_request = Some(StateRequest::Save(self.slot));
+            }
+            if self.hotkeys.load_state == name {
+                self.state_request = Some(StateRequest::Load(self.slot));
+            }
+            if self.hotkeys.record == name {
+                self.record_toggled = true;
+            }
+        }
+        if self.hotkeys.rewind == name {
+            self.rewind_held = pressed;
+        }
+        if let Some(chip8_key) = name.and_then(|name| self.keymap.key(&name)) {
+            self.held[chip8_key as usize] = pressed;
+        }
+    }
+
+    // let go of the keys that weren't repeated lately
+    fn release_keys(&mut self) {
+        if self.releases {
+            return;
+        }
+        let now = Instant::now();
+        for pressed in self.held.iter_mut().chain([&mut self.rewind_held]) {
+            if pressed.is_some_and(|pressed| now - pressed > KEY_HOLD) {
+                *pressed = None;
+            }
+        }
+    }
+
+    fn redraw(&mut self) -> io::Result<()> {
+        let (width, height) = self.resolution;
+        let mut out = Vec::new();
+        if self.drawn.len() != self.pixel_colors.len() {
+            // nothing on the terminal can be reused after a resolution change
+            self.drawn = vec![u8::MAX; self.pixel_colors.len()];
+            self.status.clear();
+            queue!(out, ResetColor, Clear(ClearType::All))?;
+        }
+        let mut colors = None;
+        let mut cursor = None;
+        for row in 0..height / 2 {
+            for col in 0..width {
+                let (top, bottom) = (2 * row * width + col, (2 * row + 1) * width + col);
+                let pair = (self.pixel_colors[top], self.pixel_colors[bottom]);
+                if (self.drawn[top], self.drawn[bottom]) == pair {
+                    continue;
+                }
+                (self.drawn[top], self.drawn[bottom]) = pair;
+                if cursor != Some((col, row)) {
+                    queue!(out, MoveTo(col as u16, row as u16))?;
+                }
+                if colors != Some(pair) {
+                    queue!(
+                        out,
+                        SetForegroundColor(self.color(pair.0)),
+                        SetBackgroundColor(self.color(pair.1))
+                    )?;
+                    colors = Some(pair);
+                }
+                queue!(out, Print('\u{2580}'))?;
+                cursor = Some((col + 1, row));
+            }
+        }
+        queue!(out, ResetColor)?;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&out)?;
+        stdout.flush()
+    }
+
+    fn draw_status(&mut self, lines: Vec<String>) -> io::Result<()> {
+        if lines == self.status {
+            return Ok(());
+        }
+        let top = (self.resolution.1 / 2) as u16;
+        let mut stdout = io::stdout().lock();
+        for (row, line) in lines.iter().enumerate() {
+            queue!(
+                stdout,
+                MoveTo(0, top + row as u16),
+                Print(line),
+                Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        self.status = lines;
+        stdout.flush()
+    }
+
+    fn color(&self, index: u8) -> Color {
+        let [r, g, b] = self.palette.color(index);
+        Color::Rgb { r, g, b }
+    }
+}
+
+// name of the SFML key typing the same character, as used by keymaps and hotkeys
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(letter) if letter.is_ascii_alphabetic() => {
+            return Some(letter.to_ascii_uppercase().to_string())
+        }
+        KeyCode::Char(digit) if digit.is_ascii_digit() => return Some(format!("Num{}", digit)),
+        KeyCode::F(number) => return Some(format!("F{}", number)),
+        KeyCode::Char(' ') => "Space",
+        KeyCode::Char(',') => "Comma",
+        KeyCode::Char('.') => "Period",
+        KeyCode::Char('-') => "Hyphen",
+        KeyCode::Char('=') => "Equal",
+        KeyCode::Char(';') => "Semicolon",
+        KeyCode::Char('\'') => "Quote",
+        KeyCode::Char('/') => "Slash",
+        KeyCode::Char('\\') => "Backslash",
+        KeyCode::Char('[') => "LBracket",
+        KeyCode::Char(']') => "RBracket",
+        KeyCode::Char('`') => "Tilde",
+        KeyCode::Char('+') => "Add",
+        KeyCode::Char('*') => "Multiply",
+        KeyCode::Enter => "Enter",
+        KeyCode::Backspace => "Backspace",
+        KeyCode::Tab => "Tab",
+        KeyCode::Esc => "Escape",
+        KeyCode::Left => "Left",
+        KeyCode::Right => "Right",
+        KeyCode::Up => "Up",
+        KeyCode::Down => "Down",
+        KeyCode::Home => "Home",
+        KeyCode::End => "End",
+        KeyCode::PageUp => "PageUp",
+        KeyCode::PageDown => "PageDown",
+        KeyCode::Insert => "Insert",
+        KeyCode::Delete => "Delete",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // restore the terminal even when the session ends with an error
+        if self.enhanced {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Display for Terminal {
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.pixel_colors = framebuffer.colors();
+        self.resolution = (framebuffer.width(), framebuffer.height());
+        // a terminal that can't be written to anymore (closed ssh session) ends the session
+        if self.redraw().is_err() {
+            self.quit_flag = true;
+        }
+    }
+}
+
+impl Keypad for Terminal {
+    fn handle_events(&mut self) {
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            match event::read() {
+                Ok(Event::Key(key)) => self.key_event(key),
+                Ok(Event::Resize(_, _)) => {
+                    self.drawn.clear();
+                    if self.redraw().is_err() {
+                        self.quit_flag = true;
+                    }
+                }
+                Ok(_) => (),
+                Err(_) => {
+                    self.quit_flag = true;
+                    break;
+                }
+            }
+        }
+        self.release_keys();
+    }
+
+    fn key_flags(&self) -> u16 {
+        self.held
+            .iter()
+            .enumerate()
+            .filter(|(_, pressed)| pressed.is_some())
+            .fold(0, |flags, (key, _)| flags | 1u16 << key)
+    }
+
+    fn closed(&self) -> bool {
+        self.quit_flag
+    }
+}
+
+/*
+Bell:
+    - rings the terminal bell whenever the beeper starts, the closest a terminal gets to sound
+      in builds without sfml
+    muted:
+        - never ring
+    playing:
+        - the beeper is on
+*/
+#[cfg(not(feature = "sfml"))]
+pub struct Bell {
+    muted: bool,
+    playing: bool,
+}
+
+#[cfg(not(feature = "sfml"))]
+impl Bell {
+    pub fn new(muted: bool) -> Self {
+        Bell {
+            muted,
+            playing: false,
+        }
+    }
+}
+
+#[cfg(not(feature = "sfml"))]
+impl Speaker for Bell {
+    fn play(&mut self, sound: Sound) {
+        let playing = sound != Sound::Silence;
+        if playing && !self.playing && !self.muted {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+        }
+        self.playing = playing;
+    }
+}
+
+impl Frontend for Terminal {
+    fn take_state_request(&mut self) -> Option<StateRequest> {
+        self.state_request.take()
+    }
+
+    fn take_record_toggle(&mut self) -> bool {
+        std::mem::take(&mut self.record_toggled)
+    }
+
+    fn rewinding(&self) -> bool {
+        self.rewind_held.is_some()
+    }
+
+    fn show_machine(&mut self, chip8: &Chip8) {
+        let memory = chip8.memory();
+        let registers: Vec<String> = memory
+            .var_registers()
+            .iter()
+            .map(|register| format!("{:02X}", register))
+            .collect();
+        let lines = vec![
+            format!(
+                "PC {:04X}  I {:04X}  DT {:02X}  ST {:02X}  {}",
+                memory.pc(),
+                memory.index_register(),
+                memory.delay_register(),
+                memory.sound_register(),
+                if chip8.sound_playing() { "beep" } else { "" }
+            ),
+            format!("V0-VF {}", registers.join(" ")),
+            self.message.clone(),
+        ];
+        if self.draw_status(lines).is_err() {
+            self.quit_flag = true;
+        }
+    }
+
+    fn show_message(&mut self, message: &str) {
+        self.message = message.to_string();
+    }
+}
@@ -0,0 +1,176 @@
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u32 = 60;
+
+// COSMAC VIP: 1.76064MHz / 8 clocks per machine cycle / 60Hz
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+// machine cycles of every frame taken by the CDP1861 display dma (128 lines * 8 bytes)
+// and the interpreter's interrupt routine, they aren't available to the chip8 program
+pub const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+// machine cycles of the interpreter's fetch and decode loop, paid by every instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+
+// a host stall longer than this (window dragged, process suspended) is dropped instead of caught up
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+// How the instructions of a frame are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    // a fixed number of instructions per frame
+    Instructions(u32),
+    // as many instructions as fit into a frame of COSMAC VIP machine cycles
+    VipCycles,
+}
+
+/*
+FrameClock:
+    - fixed timestep pacing of the 60Hz frame loop
+    frame:
+        - duration of one emulated frame
+    last:
+        - when frames_due was last called
+    lag:
+        - real time passed that hasn't been emulated yet
+*/
+pub struct FrameClock {
+    frame: Duration,
+    last: Instant,
+    lag: Duration,
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        FrameClock {
+            frame: Duration::from_secs(1) / FRAME_RATE,
+            last: Instant::now(),
+            lag: Duration::ZERO,
+        }
+    }
+
+    // number of whole frames to emulate to catch up with real time
+    pub fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+        self.lag = (self.lag + (now - self.last)).min(MAX_LAG);
+        self.last = now;
+
+        let mut frames = 0;
+        while self.lag >= self.frame {
+            self.lag -= self.frame;
+            frames += 1;
+        }
+        frames
+    }
+
+    // time the host can sleep until the next frame is due
+    pub fn until_next_frame(&self) -> Duration {
+        self.frame.saturating_sub(self.lag + self.last.elapsed())
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// COSMAC VIP machine cycles taken by `instruction`, given the state before it is executed
+// instructions the VIP interpreter doesn't know only cost the fetch
+pub fn vip_cycles(instruction: Instruction, memory: &Memory, key_flags: u16) -> u32 {
+    let var = |x: u8| memory.get_var_register(x).unwrap_or_default();
+    let skip = |skipped: bool| if skipped { 4 } else { 0 };
+    let key_pressed = |x: u8| key_flags & (1 << (var(x) & 0xF)) != 0;
+
+    VIP_FETCH_CYCLES
+        + match instruction {
+            // 00E0: clears the 256 byte display buffer
+            Instruction::ClearScreen => 3078,
+            Instruction::Return => 10,
+            Instruction::Jump { .. } => 12,
+            Instruction::Call { .. } => 26,
+            Instruction::SkipIfEqImm { x, nn } => 10 + skip(var(x) == nn),
+            Instruction::SkipIfNeqImm { x, nn } => 10 + skip(var(x) != nn),
+            Instruction::SkipIfEq { x, y } => 14 + skip(var(x) == var(y)),
+            Instruction::SkipIfNeq { x, y } => 14 + skip(var(x) != var(y)),
+            Instruction::SetImm { .. } => 6,
+            Instruction::AddImm { .. } => 10,
+            Instruction::Set { .. } => 12,
+            Instruction::Or { .. }
+            | Instruction::And { .. }
+            | Instruction::Xor { .. }
+            | Instruction::Add { .. }
+            | Instruction::SubXY { .. }
+            | Instruction::ShiftRight { .. }
+            | Instruction::SubYX { .. }
+            | Instruction::ShiftLeft { .. } => 44,
+            Instruction::SetIndex { .. } => 12,
+            // BNNN: crossing a page costs an extra 2 cycles
+            Instruction::JumpWithOffset { nnn, .. } => {
+                22 + if (nnn & 0xFF) + var(0) as u16 > 0xFF {
+                    2
+                } else {
+                    0
+                }
+            }
+            Instruction::Random { .. } => 36,
+            // DXYN: every row is shifted into place bit by bit before it is xored into the display
+            Instruction::DrawSprite { x, n, .. } => 26 + n as u32 * (46 + 8 * (var(x) as u32 % 8)),
+            Instruction::SkipIfKey { x } => 14 + skip(key_pressed(x)),
+            Instruction::SkipIfNotKey { x } => 14 + skip(!key_pressed(x)),
+            Instruction::GetDelay { .. } => 10,
+            Instruction::WaitForKey { .. } => 19,
+            Instruction::SetDelay { .. } | Instruction::SetSound { .. } => 10,
+            Instruction::AddToIndex { .. } | Instruction::FontChar { .. } => 16,
+            // FX33: each digit is found by repeated subtraction
+            Instruction::ToDigits { x } => {
+                let vx = var(x) as u32;
+                80 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10)
+            }
+            Instruction::StoreRegisters { x } | Instruction::LoadRegisters { x } => {
+                14 + 14 * (x as u32 + 1)
+            }
+            _ => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vip_cycles_depend_on_the_operands() {
+        let mut memory = Memory::new();
+        memory.set_var_register(0, 3).unwrap();
+        memory.set_var_register(1, 123).unwrap();
+        let cycles = |instruction: Instruction, memory: &Memory| vip_cycles(instruction, memory, 0);
+
+        assert_eq!(cycles(Instruction::SetImm { x: 0, nn: 0 }, &memory), 46);
+        assert_eq!(cycles(Instruction::ClearScreen, &memory), 3118);
+        // taken skips cost 4 more cycles
+        assert_eq!(
+            cycles(Instruction::SkipIfEqImm { x: 0, nn: 3 }, &memory),
+            54
+        );
+        assert_eq!(
+            cycles(Instruction::SkipIfEqImm { x: 0, nn: 4 }, &memory),
+            50
+        );
+        // 5 rows shifted by 3 bits
+        let draw = Instruction::DrawSprite { x: 0, y: 1, n: 5 };
+        assert_eq!(cycles(draw, &memory), 40 + 26 + 5 * (46 + 8 * 3));
+        // 1 + 2 + 3 subtractions
+        assert_eq!(
+            cycles(Instruction::ToDigits { x: 1 }, &memory),
+            40 + 80 + 16 * 6
+        );
+        // 0xFD + v0 crosses a page
+        let jump = Instruction::JumpWithOffset { x: 0, nnn: 0x2FD };
+        assert_eq!(cycles(jump, &memory), 64);
+        // SUPER-CHIP instructions only cost the fetch
+        assert_eq!(cycles(Instruction::HighRes, &memory), 40);
+        // the key of v1 & 0xF (0xB) is pressed
+        let skip_key = Instruction::SkipIfKey { x: 1 };
+        assert_eq!(vip_cycles(skip_key, &memory, 1 << 0xB), 58);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::debugger::op_code_at;
+use crate::error::Chip8Error;
+use crate::instruction::{decode, Instruction};
+use crate::machine::Chip8;
+use crate::timing::Scheduler;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+// Layout of the trace, one line per executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    JsonLines,
+}
+
+// State after an executed instruction
+#[derive(Serialize)]
+struct TraceEntry<'a> {
+    cycle: u64,
+    pc: u16,
+    opcode: u16,
+    mnemonic: String,
+    v: &'a [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+    stack: &'a [u16],
+}
+
+/*
+Tracer:
+    - logs every executed instruction, e.g. to diff runs against a reference emulator
+    writer:
+        - destination of the trace
+    format:
+        - text or json lines
+*/
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        Tracer { writer, format }
+    }
+
+    // log the instruction at `pc` which chip8 just executed
+    pub fn trace(&mut self, pc: u16, op_code: u16, chip8: &Chip8) -> io::Result<()> {
+        let memory = chip8.memory();
+        let entry = TraceEntry {
+            cycle: chip8.instruction_count(),
+            pc,
+            opcode: op_code,
+            mnemonic: mnemonic(pc, op_code, chip8),
+            v: memory.var_registers(),
+            i: memory.index_register(),
+            dt: memory.delay_register(),
+            st: memory.sound_register(),
+            stack: memory.stack(),
+        };
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", entry),
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &entry)?;
+                writeln!(self.writer)
+            }
+        }
+    }
+
+    // Chip8::step_in_frame, tracing the instruction if one was executed
+    pub fn step_in_frame(
+        &mut self,
+        chip8: &mut Chip8,
+        scheduler: Scheduler,
+    ) -> Result<bool, Chip8Error> {
+        let pc = chip8.memory().pc();
+        let op_code = op_code_at(chip8.memory(), pc).unwrap_or_default();
+        let instruction_count = chip8.instruction_count();
+        let frame_done = chip8.step_in_frame(scheduler)?;
+        if chip8.instruction_count() != instruction_count {
+            self.trace(pc, op_code, chip8)?;
+        }
+        Ok(frame_done)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// F000 NNNN shows the address of its second word, which is still in memory after it ran
+fn mnemonic(pc: u16, op_code: u16, chip8: &Chip8) -> String {
+    match decode(op_code) {
+        Ok(Instruction::LoadLongIndex) => {
+            let address = op_code_at(chip8.memory(), pc.wrapping_add(2)).unwrap_or_default();
+            format!("LD I, {:#06X}", address)
+        }
+        Ok(instruction) => instruction.mnemonic(chip8.quirks()),
+        Err(_) => "???".to_string(),
+    }
+}
+
+// cycle pc opcode mnemonic V0-VF I DT ST stack
+// 00000001 0200 00E0 CLS                  V=00000000000000000000000000000000 I=0000 DT=00 ST=00 S=
+impl fmt::Display for TraceEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:08} {:04X} {:04X} {:<20} V=",
+            self.cycle, self.pc, self.opcode, self.mnemonic
+        )?;
+        for v in self.v {
+            write!(f, "{:02X}", v)?;
+        }
+        write!(
+            f,
+            " I={:04X} DT={:02X} ST={:02X} S=",
+            self.i, self.dt, self.st
+        )?;
+        let stack: Vec<String> = self
+            .stack
+            .iter()
+            .map(|address| format!("{:04X}", address))
+            .collect();
+        write!(f, "{}", stack.join(","))
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::JsonLines),
+            _ => Err(format!(
+                "unknown trace format: {}, expected text or json",
+                format
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::Mode;
+    use crate::quirks::Quirks;
+
+    // the trace of running `rom` for `steps` instructions
+    fn trace(format: TraceFormat, rom: &[u8], steps: usize) -> String {
+        let mut chip8 = Chip8::new(Mode::XoChip, Quirks::for_mode(Mode::XoChip));
+        chip8.load_rom_bytes(rom).unwrap();
+        let mut tracer = Tracer::new(vec![], format);
+        for _ in 0..steps {
+            tracer
+                .step_in_frame(&mut chip8, Scheduler::Instructions(100))
+                .unwrap();
+        }
+        String::from_utf8(tracer.writer).unwrap()
+    }
+
+    #[test]
+    fn text_lines_show_the_state_after_the_instruction() {
+        // v3 := 0x2A, i := long 0x1234
+        let rom = [0x63, 0x2A, 0xF0, 0x00, 0x12, 0x34];
+        let lines = trace(TraceFormat::Text, &rom, 2);
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(
+            lines[1],
+            "00000002 0202 F000 LD I, 0x1234         \
+             V=0000002A000000000000000000000000 I=1234 DT=00 ST=00 S="
+        );
+    }
+
+    #[test]
+    fn json_lines_hold_one_record_per_instruction() {
+        let lines = trace(TraceFormat::JsonLines, &[0x63, 0x2A], 1);
+        assert_eq!(
+            lines,
+            "{\"cycle\":1,\"pc\":512,\"opcode\":25386,\"mnemonic\":\"LD V3, 0x2A\",\
+             \"v\":[0,0,0,42,0,0,0,0,0,0,0,0,0,0,0,0],\"i\":0,\"dt\":0,\"st\":0,\"stack\":[]}\n"
+        );
+    }
+}
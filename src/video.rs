@@ -0,0 +1,373 @@
+use crate::error::Chip8Error;
+use crate::framebuffer::{Framebuffer, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+use crate::image::scaled_colors;
+use crate::palette::Palette;
+use crate::timing::FRAME_RATE;
+use gif::{DisposalMethod, Encoder, Frame, Repeat};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// browsers show frames with shorter delays for a tenth of a second
+const MIN_DELAY: u16 = 2;
+
+/*
+VideoRecorder:
+    - writes every captured frame into an animated GIF or a raw YUV4MPEG2 (Y4M) stream
+    - the picture has the size of the largest screen of the mode, smaller screens are scaled up
+    output:
+        - GIF encoder or Y4M stream
+    palette:
+        - colors of the recording
+    size:
+        - (width,height) of the picture in pixels
+    frames:
+        - captured frames
+*/
+pub struct VideoRecorder {
+    output: Output,
+    palette: Palette,
+    size: (usize, usize),
+    frames: u64,
+}
+
+enum Output {
+    Gif(GifOutput),
+    Y4m { writer: Box<dyn Write> },
+}
+
+/*
+GifOutput:
+    - only the changed part of the picture is stored for a frame
+    written:
+        - colors of the picture the frames written so far add up to
+    pending:
+        - colors of the newest picture and the frame it appeared in, written once its delay is known
+*/
+struct GifOutput {
+    encoder: Encoder<Box<dyn Write>>,
+    size: (usize, usize),
+    written: Vec<u8>,
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+impl VideoRecorder {
+    // GIF for paths ending in .gif, Y4M for .y4m and for - (stdout)
+    pub fn create(
+        path: &Path,
+        palette: Palette,
+        scale: u32,
+        hires: bool,
+    ) -> Result<Self, Chip8Error> {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (LORES_WIDTH, LORES_HEIGHT)
+        };
+        let size = (width * scale as usize, height * scale as usize);
+        let stdout = path == Path::new("-");
+        let gif = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gif") => true,
+            Some("y4m") => false,
+            _ if stdout => false,
+            _ => {
+                return Err(Chip8Error::Config {
+                    message: format!(
+                        "can't record to {}, expected a .gif or .y4m file or - for stdout",
+                        path.display()
+                    ),
+                })
+            }
+        };
+        let gif_size = if gif { Some(gif_size(size)?) } else { None };
+        let mut writer: Box<dyn Write> = if stdout {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        let output = if let Some((gif_width, gif_height)) = gif_size {
+            let colors: Vec<u8> = palette.colors.iter().flatten().copied().collect();
+            let mut encoder =
+                Encoder::new(writer, gif_width, gif_height, &colors).map_err(gif_error)?;
+            encoder.set_repeat(Repeat::Infinite).map_err(gif_error)?;
+            Output::Gif(GifOutput {
+                encoder,
+                size,
+                written: vec![],
+                pending: None,
+            })
+        } else {
+            writeln!(
+                writer,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                size.0, size.1, FRAME_RATE
+            )?;
+            Output::Y4m { writer }
+        };
+        Ok(VideoRecorder {
+            output,
+            palette,
+            size,
+            frames: 0,
+        })
+    }
+
+    // called after every frame
+    pub fn capture(&mut self, framebuffer: &Framebuffer) -> Result<(), Chip8Error> {
+        let scale = (self.size.0 / framebuffer.width()) as u32;
+        let colors = scaled_colors(framebuffer, scale);
+        let frame = self.frames;
+        self.frames += 1;
+        match &mut self.output {
+            Output::Gif(gif) => gif.capture(colors, frame)?,
+            Output::Y4m { writer } => {
+                // planar 4:4:4, all Y values, then all U values, then all V values
+                let yuv = self.palette.colors.map(rgb_to_yuv);
+                let planes: Vec<u8> = (0..3)
+                    .flat_map(|plane| colors.iter().map(move |color| yuv[*color as usize][plane]))
+                    .collect();
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&planes)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // write what is still buffered, the recording is complete afterwards
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        match self.output {
+            Output::Gif(mut gif) => {
+                if let Some((colors, start)) = gif.pending.take() {
+                    gif.write(colors, start, self.frames)?;
+                }
+                gif.encoder.into_inner()?.flush()?;
+            }
+            Output::Y4m { mut writer } => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl GifOutput {
+    fn capture(&mut self, colors: Vec<u8>, frame: u64) -> Result<(), Chip8Error> {
+        let newest = self
+            .pending
+            .as_ref()
+            .map_or(&self.written, |(colors, _)| colors);
+        if *newest == colors {
+            return Ok(());
+        }
+        match self.pending.take() {
+            // the change is shown a frame early rather than too briefly
+            Some((_, start)) if delay(start, frame) < MIN_DELAY => {
+                self.pending = Some((colors, start))
+            }
+            Some((pending, start)) => {
+                self.write(pending, start, frame)?;
+                self.pending = Some((colors, frame));
+            }
+            None => self.pending = Some((colors, frame)),
+        }
+        Ok(())
+    }
+
+    // the picture shown from frame start until frame end
+    fn write(&mut self, colors: Vec<u8>, start: u64, end: u64) -> Result<(), Chip8Error> {
+        // a picture changed back to the written one still needs a frame to keep the timing
+        let area = changed_area(&self.written, &colors, self.size).unwrap_or((0, 0, 1, 1));
+        let mut frame = crop(&colors, self.size, area);
+        frame.delay = delay(start, end).max(MIN_DELAY);
+        self.encoder.write_frame(&frame).map_err(gif_error)?;
+        self.written = colors;
+        Ok(())
+    }
+}
+
+// GIFs store their size in 16 bits, checked before the file is created
+fn gif_size((width, height): (usize, usize)) -> Result<(u16, u16), Chip8Error> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(Chip8Error::Config {
+            message: format!(
+                "a {}x{} picture is too large for a GIF, lower the scale",
+                width, height
+            ),
+        }),
+    }
+}
+
+// GIF delays are in hundredths of a second, frame n starts at n * 100 / 60 rounded down
+fn delay(start: u64, end: u64) -> u16 {
+    let centiseconds = |frame: u64| frame * 100 / FRAME_RATE as u64;
+    (centiseconds(end) - centiseconds(start)).min(u16::MAX as u64) as u16
+}
+
+// (left, top, width, height) of the pixels that differ, everything if there is no previous frame
+fn changed_area(
+    previous: &[u8],
+    colors: &[u8],
+    (width, height): (usize, usize),
+) -> Option<(usize, usize, usize, usize)> {
+    if previous.len() != colors.len() {
+        return Some((0, 0, width, height));
+    }
+    let changed = |index: &usize| previous[*index] != colors[*index];
+    let first = (0..colors.len()).find(changed)?;
+    let last = (0..colors.len()).rev().find(changed)?;
+    let (top, bottom) = (first / width, last / width);
+    let columns = (top * width..=bottom * width + width - 1)
+        .filter(changed)
+        .map(|index| index % width);
+    let (left, right) = columns.fold((width, 0), |(left, right), column| {
+        (left.min(column), right.max(column))
+    });
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+fn crop(
+    colors: &[u8],
+    (width, _): (usize, usize),
+    (left, top, area_width, area_height): (usize, usize, usize, usize),
+) -> Frame<'static> {
+    let buffer: Vec<u8> = (top..top + area_height)
+        .flat_map(|y| {
+            colors[y * width + left..y * width + left + area_width]
+                .iter()
+                .copied()
+        })
+        .collect();
+    Frame {
+        left: left as u16,
+        top: top as u16,
+        width: area_width as u16,
+        height: area_height as u16,
+        dispose: DisposalMethod::Keep,
+        buffer: buffer.into(),
+        ..Frame::default()
+    }
+}
+
+// BT.601 studio range
+fn rgb_to_yuv([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+fn gif_error(err: gif::EncodingError) -> Chip8Error {
+    match err {
+        gif::EncodingError::Io(err) => Chip8Error::Io(err),
+        err => Chip8Error::Io(io::Error::other(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a writer that can still be read after the encoder took it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn gifs_larger_than_16_bits_are_rejected() {
+        let path = Path::new("missing/recording.gif");
+        let err = VideoRecorder::create(path, Palette::default(), 600, true);
+        assert!(matches!(err, Err(Chip8Error::Config { .. })));
+    }
+
+    #[test]
+    fn changed_area_crops_to_the_differing_pixels() {
+        let size = (4, 3);
+        let blank = vec![0; 12];
+        let with = |pixels: &[(usize, usize)]| {
+            let mut colors = blank.clone();
+            for (x, y) in pixels {
+                colors[y * 4 + x] = 1;
+            }
+            colors
+        };
+        assert_eq!(changed_area(&[], &blank, size), Some((0, 0, 4, 3)));
+        assert_eq!(changed_area(&blank, &blank, size), None);
+        assert_eq!(
+            changed_area(&blank, &with(&[(2, 1)]), size),
+            Some((2, 1, 1, 1))
+        );
+        assert_eq!(
+            changed_area(&blank, &with(&[(1, 0), (3, 2)]), size),
+            Some((1, 0, 3, 3))
+        );
+        assert_eq!(
+            changed_area(&with(&[(3, 0)]), &with(&[(0, 1)]), size),
+            Some((0, 0, 4, 2))
+        );
+    }
+
+    #[test]
+    fn short_lived_pictures_are_merged_into_the_next_frame() {
+        let output = Shared::default();
+        let palette: Vec<u8> = Palette::default()
+            .colors
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+        let encoder: Box<dyn Write> = Box::new(output.clone());
+        let mut gif = GifOutput {
+            encoder: Encoder::new(encoder, 4, 2, &palette).unwrap(),
+            size: (4, 2),
+            written: vec![],
+            pending: None,
+        };
+        let blank = vec![0; 8];
+        let mut first = blank.clone();
+        first[1] = 1;
+        let mut second = first.clone();
+        second[6] = 1;
+
+        gif.capture(blank, 0).unwrap();
+        // shown for a single frame, less than MIN_DELAY
+        gif.capture(first.clone(), 1).unwrap();
+        assert_eq!(gif.pending, Some((first.clone(), 0)));
+        gif.capture(first.clone(), 2).unwrap();
+        gif.capture(second.clone(), 4).unwrap();
+        assert_eq!(gif.written, first);
+        assert_eq!(gif.pending, Some((second.clone(), 4)));
+        let (colors, start) = gif.pending.take().unwrap();
+        gif.write(colors, start, 6).unwrap();
+        drop(gif);
+
+        let bytes = output.0.borrow().clone();
+        let mut decoder = gif::DecodeOptions::new().read_info(&bytes[..]).unwrap();
+        let mut frames = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((
+                frame.delay,
+                frame.left,
+                frame.top,
+                frame.width,
+                frame.height,
+            ));
+        }
+        // frame 0 until 4 and frame 4 until 6, in hundredths of a second
+        assert_eq!(frames, [(6, 0, 0, 4, 2), (4, 2, 1, 1, 1)]);
+    }
+}